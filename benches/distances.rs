@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use txlsh::{BucketKind, ChecksumKind, TxLsh, TxLshBuilder, TxLshSlab, Version};
+
+fn digests(count: usize) -> Vec<TxLsh> {
+    let mut state = 1u64;
+    let mut data = vec![0; 512];
+
+    (0..count)
+        .map(|_| {
+            for byte in data.iter_mut() {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                *byte = (state >> 56) as u8;
+            }
+
+            let mut builder = TxLshBuilder::new(
+                BucketKind::Bucket256,
+                ChecksumKind::ThreeByte,
+                Version::Version4,
+            );
+            builder.update(&data);
+            builder.build().unwrap()
+        })
+        .collect()
+}
+
+fn distances(c: &mut Criterion) {
+    let digests = digests(10_000);
+    let mut slab = TxLshSlab::new();
+    for digest in &digests {
+        slab.push(digest).unwrap();
+    }
+    let query = &digests[0];
+
+    let mut group = c.benchmark_group("distances");
+    group.throughput(Throughput::Elements(digests.len() as u64));
+    group.bench_function("diff", |b| {
+        b.iter(|| {
+            digests
+                .iter()
+                .map(|d| query.diff(d, true))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("slab", |b| b.iter(|| slab.distances(query, true).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, distances);
+criterion_main!(benches);
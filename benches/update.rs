@@ -0,0 +1,97 @@
+use criterion::{
+    criterion_group, criterion_main, BenchmarkId, Criterion, SamplingMode, Throughput,
+};
+use txlsh::{BucketKind, ChecksumKind, TxLshBuilder, Version};
+
+fn data(len: usize) -> Vec<u8> {
+    let mut state = 1u64;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+fn update(c: &mut Criterion) {
+    let data = data(1 << 20);
+    let variants = [
+        (
+            "T1-128-1",
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        ),
+        (
+            "T1-256-3",
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::Version4,
+        ),
+        (
+            "X1-256-3",
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV1,
+        ),
+        (
+            "X2-256-3",
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV2,
+        ),
+    ];
+
+    let mut group = c.benchmark_group("update");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    // Every sample runs the same number of iterations, so that runs can be compared.
+    group.sampling_mode(SamplingMode::Flat).sample_size(20);
+
+    for (name, bucket, checksum, ver) in variants {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &data, |b, data| {
+            b.iter(|| {
+                let mut builder = TxLshBuilder::new(bucket, checksum, ver);
+                builder.update(data);
+                builder.build().unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn batch(c: &mut Criterion) {
+    let data = data(10_000 * 200);
+    let inputs: Vec<&[u8]> = data.chunks(200).collect();
+    let template = TxLshBuilder::new(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+    );
+
+    let mut group = c.benchmark_group("batch");
+    group.throughput(Throughput::Elements(inputs.len() as u64));
+
+    group.bench_function("one-by-one", |b| {
+        b.iter(|| {
+            inputs
+                .iter()
+                .map(|input| {
+                    let mut builder = template.clone();
+                    builder.update(input);
+                    builder.build()
+                })
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("build_batch", |b| {
+        b.iter(|| template.build_batch(inputs.iter().copied()))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, update, batch);
+criterion_main!(benches);
@@ -1,52 +1,38 @@
-use std::{
-    collections::HashMap,
-    env,
-    fs::{read_dir, File},
-    io::{BufReader, Read},
-    path::Path,
-};
+use std::{collections::HashMap, env, fs::read_dir, path::Path};
 
 use txlsh::TxLshBuilder;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let dir = Path::new(args.get(1).unwrap());
-    let mut buffer = [0; 1024];
     let mut hm = HashMap::new();
 
+    let builder = TxLshBuilder::new(
+        txlsh::BucketKind::Bucket128,
+        txlsh::ChecksumKind::OneByte,
+        txlsh::Version::Version4,
+    );
     for entry in read_dir(dir).unwrap() {
-        assert!(
-            entry.is_ok(),
-            "Error reading file: {:?}",
-            entry.err().unwrap()
-        );
-
-        let d = entry.unwrap();
-        let pb = d.path();
-
-        if pb.is_file() {
-            let mut builder = TxLshBuilder::new(
-                txlsh::BucketKind::Bucket128,
-                txlsh::ChecksumKind::OneByte,
-                txlsh::Version::Version4,
-            );
-            let file = File::open(&pb).unwrap();
-            let mut reader = BufReader::new(file);
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => {
+                eprintln!("Failed to read directory {:?}: {}", dir, err);
+                continue;
+            }
+        };
+        if !path.is_file() {
+            continue;
+        }
 
-            let mut byte_read = 1;
-            while byte_read != 0 {
-                match reader.read(&mut buffer) {
-                    Ok(n) => {
-                        byte_read = n;
-                        builder.update_from(&buffer, 0, byte_read);
-                    }
-                    Err(_) => panic!("Failed to read file: {:?}", &pb),
-                };
+        let mut builder = builder.clone();
+        let digest = builder
+            .update_file(&path, |_, _| (), None)
+            .and_then(|_| builder.build());
+        match digest {
+            Ok(digest) => {
+                hm.insert(path.display().to_string(), digest);
             }
-            hm.insert(
-                pb.as_os_str().to_os_string().into_string().unwrap(),
-                builder.build().unwrap(),
-            );
+            Err(err) => eprintln!("Failed to hash file {:?}: {}", path, err),
         }
     }
     for (p1, tlsh1) in &hm {
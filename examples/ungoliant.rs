@@ -1,52 +1,23 @@
-use std::{
-    collections::HashMap,
-    env,
-    fs::{read_dir, File},
-    io::{BufReader, Read},
-    path::Path,
-};
+use std::{collections::HashMap, env, path::Path};
 
-use txlsh::TxLshBuilder;
+use txlsh::{hash_tree, TreeOptions, TxLshBuilder};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let dir = Path::new(args.get(1).unwrap());
-    let mut buffer = [0; 1024];
     let mut hm = HashMap::new();
 
-    for entry in read_dir(dir).unwrap() {
-        assert!(
-            entry.is_ok(),
-            "Error reading file: {:?}",
-            entry.err().unwrap()
-        );
-
-        let d = entry.unwrap();
-        let pb = d.path();
-
-        if pb.is_file() {
-            let mut builder = TxLshBuilder::new(
-                txlsh::BucketKind::Bucket256,
-                txlsh::ChecksumKind::ThreeByte,
-                txlsh::Version::Version4,
-            );
-            let file = File::open(&pb).unwrap();
-            let mut reader = BufReader::new(file);
-
-            let mut byte_read = 1;
-            while byte_read != 0 {
-                match reader.read(&mut buffer) {
-                    Ok(n) => {
-                        byte_read = n;
-                        builder.update_from(&buffer, 0, byte_read);
-                    }
-                    Err(_) => panic!("Failed to read file: {:?}", &pb),
-                };
+    let builder = TxLshBuilder::new(
+        txlsh::BucketKind::Bucket256,
+        txlsh::ChecksumKind::ThreeByte,
+        txlsh::Version::Version4,
+    );
+    for entry in hash_tree(dir, &TreeOptions::new(builder)).unwrap() {
+        match entry.digest {
+            Ok(digest) => {
+                hm.insert(entry.path.display().to_string(), digest);
             }
-            hm.insert(
-                pb.as_os_str().to_os_string().into_string().unwrap(),
-                builder.build().unwrap(),
-            );
+            Err(err) => eprintln!("Failed to hash file {:?}: {}", entry.path, err),
         }
     }
     for (p1, tlsh3) in &hm {
@@ -0,0 +1,153 @@
+use crate::{
+    hash_funcs::TripletHasher,
+    helper::{BucketKind, BUCKET_SIZE},
+    TxLsh, TxLshBuilder, TxLshError, TxLshHistogram,
+};
+
+/// Scale of the bucket weights once converted back into counts.
+const SCALE: f64 = (1u64 << 32) as f64;
+
+/// An aggregate of the bucket histograms of a family of inputs, from which a representative
+/// digest is built.
+///
+/// Each histogram is normalised before being added, so that every input weighs the same whatever
+/// its length. All histograms must be built with the same settings.
+#[derive(Clone, Debug, Default)]
+pub struct TxLshCentroid {
+    weights: Vec<f64>,
+    total_len: f64,
+    count: usize,
+    /// First histogram added, holding the settings of the family.
+    template: Option<TxLshHistogram>,
+}
+
+impl TxLshCentroid {
+    /// Constructs an empty centroid.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of inputs added so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Adds the histogram of an input.
+    ///
+    /// Fails with [`TxLshError::IncompatibleDigests`] if it was built with other settings than the
+    /// histograms added before.
+    pub fn add_histogram(&mut self, histogram: &TxLshHistogram) -> Result<(), TxLshError> {
+        let total = histogram.buckets.iter().sum::<u64>();
+        if total == 0 {
+            Err(TxLshError::NoValidHash)?
+        }
+
+        let template = self.template.get_or_insert_with(|| histogram.clone());
+        if template.ver != histogram.ver
+            || template.tag != histogram.tag
+            || template.checksum_kind != histogram.checksum_kind
+        {
+            Err(TxLshError::IncompatibleDigests)?
+        }
+
+        self.weights.resize(BUCKET_SIZE, 0.);
+        for (weight, &count) in self.weights.iter_mut().zip(&histogram.buckets) {
+            *weight += count as f64 / total as f64;
+        }
+        self.total_len += histogram.data_len as f64;
+        self.count += 1;
+
+        Ok(())
+    }
+
+    /// Adds the data processed so far by a builder.
+    ///
+    /// Fails if the builder hashes a custom version without a hasher of its own, or processed
+    /// data in full after sampling.
+    pub fn add_builder<H: TripletHasher>(
+        &mut self,
+        builder: &TxLshBuilder<H>,
+    ) -> Result<(), TxLshError> {
+        builder.check_bound()?;
+        builder.check_sampled()?;
+        self.add_histogram(&builder.histogram())
+    }
+
+    /// Builds the digest of the mean histogram, with the mean length of the inputs.
+    ///
+    /// The checksum of a centroid is not derived from any data and is left as zeros.
+    pub fn build(&self, bucket_kind: BucketKind) -> Result<TxLsh, TxLshError> {
+        let template = self.template.as_ref().ok_or(TxLshError::NoValidHash)?;
+        let mut histogram = template.clone();
+
+        for (count, weight) in histogram.buckets.iter_mut().zip(&self.weights) {
+            *count = (weight / self.count as f64 * SCALE).round() as u64;
+        }
+        histogram.data_len = (self.total_len / self.count as f64).round() as u64;
+        histogram.checksum.fill(0);
+
+        histogram.to_txlsh(bucket_kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChecksumKind, Version};
+
+    static LOREM: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+
+    #[test]
+    fn test_centroid() {
+        let mut centroid = TxLshCentroid::new();
+        let mut digests = Vec::new();
+
+        for edit in [0, 150, 300] {
+            let mut data = LOREM.to_vec();
+            data[edit..edit + 40].reverse();
+
+            let mut builder = TxLshBuilder::new(
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Version4,
+            );
+            builder.update(&data);
+            centroid.add_builder(&builder).unwrap();
+            digests.push(builder.build().unwrap());
+        }
+
+        let result = centroid.build(BucketKind::Bucket128).unwrap();
+        assert_eq!(3, centroid.count());
+        let total = |x: &TxLsh| digests.iter().map(|d| x.diff(d, false)).sum::<usize>();
+
+        let medoid = TxLsh::medoid(&digests, false).unwrap().unwrap();
+        let totals: Vec<_> = digests.iter().map(total).collect();
+        assert!(totals.iter().all(|&t| totals[medoid] <= t));
+
+        let mut unrelated = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        let noise: Vec<u8> = (0..LOREM.len() as u32)
+            .map(|ii| (ii.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        unrelated.update(&noise);
+        assert!(total(&result) < total(&unrelated.build().unwrap()));
+        assert_eq!(None, TxLsh::medoid(&[], true).unwrap());
+
+        let mut other = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::TxLshV1,
+        );
+        other.update(LOREM);
+        assert!(centroid.add_builder(&other).is_err());
+
+        digests.push(other.build().unwrap());
+        assert!(matches!(
+            TxLsh::medoid(&digests, false),
+            Err(TxLshError::IncompatibleDigests)
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{hash_funcs::TripletHasher, TxLsh, TxLshBuilder, TxLshError};
+
+/// Random values mixed into the rolling hash of content-defined chunking, one per byte value.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x7478_6C73_6863_6463;
+    let mut ii = 0;
+
+    // splitmix64
+    while ii < 256 {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        table[ii] = z ^ (z >> 31);
+        ii += 1;
+    }
+
+    table
+}
+
+/// An enum determining how an input is split into chunks.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum ChunkKind {
+    /// Chunks of ```n``` bytes, the last one possibly shorter.
+    Fixed(usize),
+    /// Chunks cut where a rolling hash of the content matches, between ```min``` and ```max```
+    /// bytes and of ```avg``` bytes on average. Boundaries depend on the surrounding bytes only,
+    /// so shared content is split the same way wherever it lies in the inputs.
+    ContentDefined { min: usize, avg: usize, max: usize },
+}
+
+impl ChunkKind {
+    /// Returns the length of the chunk at the start of ```data```.
+    fn next_len(&self, data: &[u8]) -> usize {
+        match *self {
+            ChunkKind::Fixed(n) => n.min(data.len()),
+            ChunkKind::ContentDefined { min, avg, max } => {
+                // The top bits of the hash depend on the last 64 bytes, and are all zero once
+                // every avg bytes on average.
+                let shift = 64 - avg.next_power_of_two().trailing_zeros();
+                let mut hash = 0u64;
+
+                for (ii, &byte) in data.iter().enumerate().take(max) {
+                    hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+
+                    if ii + 1 >= min && hash.checked_shr(shift).unwrap_or(0) == 0 {
+                        return ii + 1;
+                    }
+                }
+
+                max.min(data.len())
+            }
+        }
+    }
+}
+
+/// The digest of a chunk of an input.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TxLshChunk {
+    /// Position of the chunk in the input.
+    pub offset: usize,
+    /// Length of the chunk in bytes.
+    pub len: usize,
+    pub digest: TxLsh,
+}
+
+/// The digests of the chunks of an input, telling which parts of an input appear in another.
+///
+/// Chunks are compared with [`TxLsh::diff`] without their length, and a chunk matches another one
+/// if their difference is at most a threshold.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TxLshChunkSet {
+    chunks: Vec<TxLshChunk>,
+}
+
+impl TxLshChunkSet {
+    /// Splits ```data``` into chunks and digests each of them with the settings of ```builder```.
+    ///
+    /// Chunks that cannot be hashed, e.g. a short last chunk, are left out of the set. Fails if
+    /// the chunk sizes are zero or inconsistent.
+    pub fn new<H: TripletHasher>(
+        builder: &TxLshBuilder<H>,
+        data: &[u8],
+        kind: ChunkKind,
+    ) -> Result<Self, TxLshError> {
+        let valid = match kind {
+            ChunkKind::Fixed(n) => n > 0,
+            ChunkKind::ContentDefined { min, avg, max } => 0 < min && min <= avg && avg <= max,
+        };
+        if !valid {
+            Err(TxLshError::InvalidChunkSize)?
+        }
+
+        let mut builder = builder.clone();
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let len = kind.next_len(&data[offset..]);
+
+            builder.reset();
+            builder.update(&data[offset..(offset + len)]);
+            if let Ok(digest) = builder.build() {
+                chunks.push(TxLshChunk {
+                    offset,
+                    len,
+                    digest,
+                });
+            }
+
+            offset += len;
+        }
+
+        Ok(Self { chunks })
+    }
+
+    /// Returns the digests of the chunks, in the order of the input.
+    pub fn chunks(&self) -> &[TxLshChunk] {
+        &self.chunks
+    }
+
+    /// Returns, for each chunk, the index of the closest chunk of ```other``` with their
+    /// difference, or ```None``` if ```other``` is empty.
+    ///
+    /// Each chunk is matched on its own, as the argmin of its differences: several chunks may
+    /// match the same chunk of ```other```, and matches need not follow the order of the chunks.
+    ///
+    /// Fails with [`TxLshError::IncompatibleDigests`] if the sets were built with different
+    /// settings, as [`TxLsh::try_diff`](crate::TxLsh::try_diff).
+    pub fn best_matches(
+        &self,
+        other: &TxLshChunkSet,
+    ) -> Result<Vec<Option<(usize, usize)>>, TxLshError> {
+        self.chunks
+            .iter()
+            .map(|chunk| {
+                let diffs = other
+                    .chunks
+                    .iter()
+                    .map(|o| chunk.digest.try_diff(&o.digest, false))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(diffs.into_iter().enumerate().min_by_key(|&(_, diff)| diff))
+            })
+            .collect()
+    }
+
+    /// Returns the fraction of the bytes of this set lying in chunks that match a chunk of
+    /// ```other```, from 0 to 1.
+    ///
+    /// Fails as [`TxLshChunkSet::best_matches`].
+    pub fn containment_in(
+        &self,
+        other: &TxLshChunkSet,
+        threshold: usize,
+    ) -> Result<f64, TxLshError> {
+        let (matched, total) = self.matched_len(other, threshold)?;
+
+        if total == 0 {
+            Ok(0.)
+        } else {
+            Ok(matched as f64 / total as f64)
+        }
+    }
+
+    /// Returns the fraction of the bytes of both sets lying in chunks that match a chunk of the
+    /// other set, from 0 to 1.
+    ///
+    /// Fails as [`TxLshChunkSet::best_matches`].
+    pub fn overlap(&self, other: &TxLshChunkSet, threshold: usize) -> Result<f64, TxLshError> {
+        let (matched_x, total_x) = self.matched_len(other, threshold)?;
+        let (matched_y, total_y) = other.matched_len(self, threshold)?;
+
+        if total_x + total_y == 0 {
+            Ok(0.)
+        } else {
+            Ok((matched_x + matched_y) as f64 / (total_x + total_y) as f64)
+        }
+    }
+
+    fn matched_len(
+        &self,
+        other: &TxLshChunkSet,
+        threshold: usize,
+    ) -> Result<(usize, usize), TxLshError> {
+        let matched = self
+            .chunks
+            .iter()
+            .zip(self.best_matches(other)?)
+            .filter(|(_, best)| matches!(best, Some((_, diff)) if *diff <= threshold))
+            .map(|(chunk, _)| chunk.len)
+            .sum();
+        let total = self.chunks.iter().map(|chunk| chunk.len).sum();
+
+        Ok((matched, total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BucketKind, ChecksumKind, Version};
+
+    fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_containment() {
+        let builder = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        let part = random_bytes(8192, 1);
+        let whole = [random_bytes(1024, 2), part.clone(), random_bytes(3072, 3)].concat();
+
+        let fixed = ChunkKind::Fixed(512);
+        let (x, y) = (
+            TxLshChunkSet::new(&builder, &part, fixed).unwrap(),
+            TxLshChunkSet::new(&builder, &whole, fixed).unwrap(),
+        );
+        assert_eq!(1., x.containment_in(&y, 0).unwrap());
+        assert_eq!(Some((2, 0)), x.best_matches(&y).unwrap()[0]);
+        assert!(y.containment_in(&x, 0).unwrap() < 0.7);
+        assert!((x.overlap(&y, 0).unwrap() - 16. * 1024. / (8192. + 12288.)).abs() < 1e-9);
+
+        // Shift the part so that fixed chunks no longer line up.
+        let shifted = [random_bytes(1000, 2), part.clone()].concat();
+        let cdc = ChunkKind::ContentDefined {
+            min: 256,
+            avg: 512,
+            max: 2048,
+        };
+        let (x, y) = (
+            TxLshChunkSet::new(&builder, &part, cdc).unwrap(),
+            TxLshChunkSet::new(&builder, &shifted, cdc).unwrap(),
+        );
+        assert!(x.containment_in(&y, 0).unwrap() > 0.8);
+
+        let xxh3 = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::TxLshV1,
+        );
+        let z = TxLshChunkSet::new(&xxh3, &part, cdc).unwrap();
+        assert!(matches!(
+            x.overlap(&z, 0),
+            Err(TxLshError::IncompatibleDigests)
+        ));
+
+        assert!(TxLshChunkSet::new(&builder, &part, ChunkKind::Fixed(0)).is_err());
+    }
+}
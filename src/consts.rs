@@ -15,8 +15,8 @@ pub(crate) const V_TABLE: [u8; 256] = [
     194, 139, 112, 43, 71, 109, 184, 209,
 ];
 
-/// documentation TODO
-pub(crate) const TOPVAL: [usize; 170] = [
+/// Upper bounds of the input lengths captured by each length code.
+pub(crate) const TOPVAL: [u64; 170] = [
     1, 2, 3, 5, 7, 11, 17, 25, 38, 57, 86, 129, 194, 291, 437, 656, 854, 1110, 1443, 1876, 2439,
     3171, 3475, 3823, 4205, 4626, 5088, 5597, 6157, 6772, 7450, 8195, 9014, 9916, 10907, 11998,
     13198, 14518, 15970, 17567, 19323, 21256, 23382, 25720, 28292, 31121, 34233, 37656, 41422,
@@ -34,3 +34,43 @@ pub(crate) const TOPVAL: [usize; 170] = [
     1223623232, 1345985727, 1480584256, 1628642751, 1791507135, 1970657856, 2167723648, 2384496256,
     2622945920, 2885240448, 3173764736, 3491141248, 3840255616, 4224281216,
 ];
+
+/// Upper bounds of the input lengths captured by each extended length code. The first entries
+/// match [`TOPVAL`], the following ones keep growing by 10% until the whole `u64` range is covered.
+pub(crate) const TOPVAL_EXTENDED: [u64; 403] = extend_topval();
+
+const fn extend_topval() -> [u64; 403] {
+    let mut table = [u64::MAX; 403];
+    let mut ii = 0;
+
+    while ii < TOPVAL.len() {
+        table[ii] = TOPVAL[ii];
+        ii += 1;
+    }
+
+    while ii < table.len() - 1 {
+        table[ii] = table[ii - 1] + table[ii - 1] / 10;
+        ii += 1;
+    }
+
+    table
+}
+
+/// Upper bounds of the input lengths captured by each short text length code. Lengths grow by one
+/// byte, then by 5% up to 64KB, so that documents of a few paragraphs spread over many codes.
+/// Beyond that, steps of 25% cover inputs of up to 4GB.
+pub(crate) const TOPVAL_SHORT: [u64; 247] = short_topval();
+
+const fn short_topval() -> [u64; 247] {
+    let mut table = [1; 247];
+    let mut ii = 1;
+
+    while ii < table.len() {
+        let prev = table[ii - 1];
+        let step = if prev < 65536 { prev / 20 } else { prev / 4 };
+        table[ii] = prev + if step > 1 { step } else { 1 };
+        ii += 1;
+    }
+
+    table
+}
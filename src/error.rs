@@ -3,16 +3,45 @@ use std::{fmt::Display, num::ParseIntError};
 /// An enum for possible errors that might occur while calculating hash values.
 #[derive(Debug)]
 pub enum TxLshError {
-    /// Input's length is too big to handle. Maximal file size is 4GB.
+    /// Input's length is too big to handle with the [`LengthKind`](crate::LengthKind) of the
+    /// builder. Inputs over 4GB need [`LengthKind::Extended`](crate::LengthKind::Extended).
     DataLenOverflow,
     /// The hash string is malformed and cannot be parsed.
     InvalidHashValue,
-    /// TLSH requires an input of at least 50 bytes.
+    /// The input is shorter than required by the [`MinSizePolicy`](crate::MinSizePolicy) of the builder.
     MinSizeNotReached,
     /// Fails to parse a hex string to integer.
     ParseHexFailed,
     // No valid hash found. See https://github.com/trendmicro/tlsh/issues/79
     NoValidHash,
+    /// The triplet selection is empty or reaches outside of the sliding window.
+    InvalidTriplets,
+    /// The input fills fewer buckets than required by the [`MinSizePolicy`](crate::MinSizePolicy) of the builder.
+    MinBucketsNotReached,
+    /// The custom version prefix is malformed or overlaps with a registered one.
+    InvalidVersion,
+    /// A custom version was used as the hasher of a builder, which has no hash function to build
+    /// with. See [`Version::Custom`](crate::Version::Custom).
+    UnboundVersion,
+    /// The digests were built with different settings or keys and cannot be compared.
+    IncompatibleDigests,
+    /// The serialised data is malformed and cannot be parsed.
+    InvalidBytes,
+    /// The capacity of a windowed builder is smaller than its sliding window.
+    InvalidCapacity,
+    /// The chunk sizes are zero or inconsistent.
+    InvalidChunkSize,
+    /// The block size of the sampling is not a power of two, or its ratio is out of range, or the
+    /// builder sampling the input already holds data or processed more data after the sample.
+    InvalidSampling,
+    /// The checksum of merged builders has not been computed yet.
+    ChecksumPending,
+    /// The hash was stopped through a [`CancelToken`](crate::CancelToken).
+    Cancelled,
+    /// Reading the input failed.
+    Io(std::io::Error),
+    /// A glob pattern of a directory scan is malformed.
+    InvalidGlob,
 }
 
 impl From<ParseIntError> for TxLshError {
@@ -21,21 +50,63 @@ impl From<ParseIntError> for TxLshError {
     }
 }
 
+impl From<std::io::Error> for TxLshError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl From<globset::Error> for TxLshError {
+    fn from(_: globset::Error) -> Self {
+        Self::InvalidGlob
+    }
+}
+
 impl Display for TxLshError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             TxLshError::DataLenOverflow => {
-                write!(f, "Input file is too big. Maximal file size is 4GB.")
+                write!(f, "Input is too long for the length code of the digest.")
             }
             TxLshError::InvalidHashValue => write!(f, "Can't parse hash string"),
             TxLshError::MinSizeNotReached => {
-                write!(f, "TLSH requires an input of at least 50 bytes.")
+                write!(f, "Input is too short for the minimum size policy.")
             }
             TxLshError::ParseHexFailed => write!(f, "Can't convert hex string to integer"),
             TxLshError::NoValidHash => write!(
                 f,
                 "No valid hash could be computed. See https://github.com/trendmicro/tlsh/issues/79"
             ),
+            TxLshError::InvalidTriplets => {
+                write!(f, "Triplet positions must lie within the sliding window.")
+            }
+            TxLshError::MinBucketsNotReached => {
+                write!(
+                    f,
+                    "Input fills too few buckets for the minimum size policy."
+                )
+            }
+            TxLshError::InvalidVersion => write!(f, "Invalid custom version prefix"),
+            TxLshError::UnboundVersion => {
+                write!(f, "A custom version must be hashed with its own hasher")
+            }
+            TxLshError::IncompatibleDigests => {
+                write!(f, "Digests built with different settings can't be compared")
+            }
+            TxLshError::InvalidBytes => write!(f, "Can't parse serialised data"),
+            TxLshError::InvalidCapacity => {
+                write!(
+                    f,
+                    "Window capacity must be at least the sliding window size."
+                )
+            }
+            TxLshError::InvalidChunkSize => write!(f, "Invalid chunk sizes"),
+            TxLshError::InvalidSampling => write!(f, "Invalid sampling parameters"),
+            TxLshError::ChecksumPending => write!(f, "The checksum of merged builders is pending"),
+            TxLshError::Cancelled => write!(f, "Hashing was cancelled"),
+            TxLshError::Io(err) => write!(f, "Can't read input: {}", err),
+            TxLshError::InvalidGlob => write!(f, "Invalid glob pattern"),
         }
     }
 }
@@ -1,16 +1,466 @@
 use crate::consts::V_TABLE;
 use crate::helper::Version;
-use xxhash_rust;
 
-/// takes four u8 values and version and apply pearson or xxhash_h
+/// A salted function mapping three bytes of the sliding window to one byte.
+///
+/// [`TxLshBuilder`](crate::TxLshBuilder) is generic over its hasher, so that the hash function is
+/// resolved at compile time instead of for every triplet. The same function also feeds the
+/// checksum, with a salt of 0 for its first byte.
+pub trait TripletHasher: Clone {
+    /// Hashes three bytes with a salt.
+    ///
+    /// Hashers resolving to [`Resolved::Wide`] hash whole windows instead, and panic.
+    fn hash(&self, salt: u8, ii: u8, jj: u8, kk: u8) -> u8;
 
-pub(crate) fn hasher(salt: u8, ii: u8, jj: u8, kk: u8, ver: Version) -> u8 {
-    match ver {
-        Version::TxLshV1 => xxhash_h(salt, ii, jj, kk),
-        _ => pearson_h(salt, ii, jj, kk),
+    /// Returns the version written at the start of the digests built with this hasher.
+    ///
+    /// User-defined hashers should return a prefix registered with [`Version::custom`] and keep
+    /// the default [`resolve`](TripletHasher::resolve).
+    fn version(&self) -> Version;
+
+    /// Returns the fingerprint of the secret key of a keyed hasher.
+    ///
+    /// The fingerprint is recorded in the digests so that digests made with different keys are
+    /// never compared by [`TxLsh::try_diff`](crate::TxLsh::try_diff).
+    fn key_fingerprint(&self) -> Option<u16> {
+        None
+    }
+
+    /// Resolves the hasher used for a whole call to
+    /// [`TxLshBuilder::update_from`](crate::TxLshBuilder::update_from).
+    ///
+    /// Hashers selected at runtime, such as [`Version`], return the built-in hasher they stand for.
+    fn resolve(&self) -> Resolved<Self> {
+        Resolved::Custom(self.clone())
+    }
+}
+
+/// A hasher resolved by [`TripletHasher::resolve`].
+///
+/// Builders dispatch on it once per update, so that each variant runs its own specialised loop.
+#[allow(clippy::large_enum_variant)]
+pub enum Resolved<H> {
+    /// The Pearson hashing of the original TLSH.
+    Pearson(Pearson),
+    /// XXH3 hashing of each triplet, used by X1 digests.
+    Xxh3(Xxh3),
+    /// XXH3 hashing of whole windows, used by X2 digests, see [`Xxh3::wide`].
+    Wide(Xxh3),
+    /// The hasher itself, called for every triplet. A hasher whose version is
+    /// [`Version::Custom`] must resolve to this variant.
+    Custom(H),
+}
+
+/// Returns whether ```hasher``` labels its digests with a custom version but hashes with a
+/// built-in function, e.g. [`Version::Custom`] used as a hasher on its own.
+pub(crate) fn is_unbound<H: TripletHasher>(hasher: &H) -> bool {
+    matches!(hasher.version(), Version::Custom(_))
+        && !matches!(hasher.resolve(), Resolved::Custom(_))
+}
+
+/// Pearson hashing over [`V_TABLE`], used by the original TLSH.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct Pearson {
+    table: [u8; 256],
+    fingerprint: Option<u16>,
+}
+
+impl Pearson {
+    /// Constructs a Pearson hasher over a permutation of [`V_TABLE`] derived from a secret key.
+    ///
+    /// Digests built with it can only be compared with digests built with the same key. The key
+    /// should have enough entropy, as its fingerprint is part of the digest.
+    pub fn keyed(key: &[u8]) -> Self {
+        let mut table = V_TABLE;
+        let mut state = xxhash_rust::xxh3::xxh3_64(key);
+
+        // Fisher-Yates shuffle driven by splitmix64.
+        for ii in (1..table.len()).rev() {
+            state = state.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            z ^= z >> 31;
+
+            table.swap(ii, (z % (ii as u64 + 1)) as usize);
+        }
+
+        Self {
+            table,
+            fingerprint: Some(key_fingerprint(key)),
+        }
+    }
+}
+
+impl Default for Pearson {
+    fn default() -> Self {
+        Self {
+            table: V_TABLE,
+            fingerprint: None,
+        }
+    }
+}
+
+impl TripletHasher for Pearson {
+    fn hash(&self, salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
+        let mut h = self.table[salt as usize];
+        h = self.table[(h ^ ii) as usize];
+        h = self.table[(h ^ jj) as usize];
+        self.table[(h ^ kk) as usize]
+    }
+
+    fn version(&self) -> Version {
+        Version::Version4
+    }
+
+    fn key_fingerprint(&self) -> Option<u16> {
+        self.fingerprint
+    }
+}
+
+/// xxh3 hashing truncated to one byte, used by TxLsh.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub struct Xxh3 {
+    seed: u64,
+    fingerprint: Option<u16>,
+    /// Whether whole windows are hashed, for X2 digests.
+    wide: bool,
+}
+
+impl Xxh3 {
+    /// Constructs an xxh3 hasher seeded from a secret key.
+    ///
+    /// Digests built with it can only be compared with digests built with the same key. The key
+    /// should have enough entropy, as its fingerprint is part of the digest.
+    pub fn keyed(key: &[u8]) -> Self {
+        Self {
+            seed: xxhash_rust::xxh3::xxh3_64(key),
+            fingerprint: Some(key_fingerprint(key)),
+            wide: false,
+        }
+    }
+
+    /// Switches to X2 digests, which hash each whole window once instead of each triplet.
+    ///
+    /// The checksum and the bucket of every triplet are taken from the bytes of a single 64-bit
+    /// hash of the window, so only the number of triplets of the selection matters, not their
+    /// positions. A wide hasher resolves to [`Resolved::Wide`] and has no hash of single triplets:
+    /// its [`TripletHasher::hash`] panics.
+    pub fn wide(self) -> Self {
+        Self { wide: true, ..self }
+    }
+}
+
+impl TripletHasher for Xxh3 {
+    fn hash(&self, salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
+        assert!(!self.wide, "X2 hashes whole windows, not triplets");
+        xxhash_rust::xxh3::xxh3_64_with_seed(&[salt, ii, jj, kk], self.seed) as u8
+    }
+
+    fn version(&self) -> Version {
+        if self.wide {
+            Version::TxLshV2
+        } else {
+            Version::TxLshV1
+        }
+    }
+
+    fn key_fingerprint(&self) -> Option<u16> {
+        self.fingerprint
+    }
+
+    fn resolve(&self) -> Resolved<Self> {
+        if self.wide {
+            Resolved::Wide(*self)
+        } else {
+            Resolved::Xxh3(*self)
+        }
+    }
+}
+
+/// Selects the hasher at runtime: [`Version::TxLshV1`] uses [`Xxh3`], [`Version::TxLshV2`] its
+/// [wide](Xxh3::wide) variant and the original versions use [`Pearson`]. Like that of a wide
+/// [`Xxh3`], the hash of single triplets panics for [`Version::TxLshV2`].
+///
+/// [`Version::Custom`] stands for no hash function. Builders using it as their hasher fail to
+/// build with [`TxLshError::UnboundVersion`](crate::TxLshError::UnboundVersion).
+impl TripletHasher for Version {
+    fn hash(&self, salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
+        match self {
+            Version::TxLshV1 => xxhash_h(salt, ii, jj, kk),
+            Version::TxLshV2 => TripletHasher::hash(&Xxh3::default().wide(), salt, ii, jj, kk),
+            _ => pearson_h(salt, ii, jj, kk),
+        }
+    }
+
+    fn version(&self) -> Version {
+        *self
+    }
+
+    fn resolve(&self) -> Resolved<Self> {
+        match self {
+            Version::TxLshV1 => Resolved::Xxh3(Xxh3::default()),
+            Version::TxLshV2 => Resolved::Wide(Xxh3::default().wide()),
+            _ => Resolved::Pearson(Pearson::default()),
+        }
+    }
+}
+
+/// Returns the byte of a packed window at a position counting back from the newest byte.
+#[inline(always)]
+pub(crate) fn window_byte(window: u64, pos: usize) -> u8 {
+    (window >> (8 * pos)) as u8
+}
+
+/// A hasher prepared for the inner loop of a builder. Windows are packed in a ```u64```, with the
+/// newest byte in the lowest one.
+pub(crate) trait Mixer {
+    /// Advances the checksum over a window.
+    fn checksum(&self, window: u64, checksum: &mut [u8]);
+
+    /// Calls ```bucket``` with the bucket of each triplet of a window.
+    fn buckets(&self, window: u64, triplets: &[(u8, usize, usize, usize)], bucket: impl FnMut(u8));
+
+    /// Advances the checksum over a window and calls ```bucket``` for each of its triplets.
+    #[inline(always)]
+    fn window(
+        &self,
+        window: u64,
+        triplets: &[(u8, usize, usize, usize)],
+        checksum: &mut [u8],
+        bucket: impl FnMut(u8),
+    ) {
+        self.checksum(window, checksum);
+        self.buckets(window, triplets, bucket);
+    }
+}
+
+/// A mixer hashing each triplet of a window separately, as every version but X2 does.
+pub(crate) trait TripletMixer {
+    /// Hashes the ```idx```-th triplet of the selection, whose salt is ```salt```.
+    fn triplet(&self, idx: usize, salt: u8, ii: u8, jj: u8, kk: u8) -> u8;
+
+    /// Hashes any triplet, e.g. the next bytes of the checksum.
+    fn hash(&self, salt: u8, ii: u8, jj: u8, kk: u8) -> u8;
+
+    /// Hashes the first byte of the checksum, salted with 0.
+    #[inline(always)]
+    fn first_checksum(&self, ii: u8, jj: u8, kk: u8) -> u8 {
+        self.hash(0, ii, jj, kk)
+    }
+}
+
+impl<T: TripletMixer> Mixer for T {
+    #[inline(always)]
+    fn checksum(&self, window: u64, checksum: &mut [u8]) {
+        let (newest, prev) = (window_byte(window, 0), window_byte(window, 1));
+
+        checksum[0] = self.first_checksum(newest, prev, checksum[0]);
+        for kk in 1..checksum.len() {
+            checksum[kk] = self.hash(checksum[kk - 1], newest, prev, checksum[kk]);
+        }
+    }
+
+    #[inline(always)]
+    fn buckets(
+        &self,
+        window: u64,
+        triplets: &[(u8, usize, usize, usize)],
+        mut bucket: impl FnMut(u8),
+    ) {
+        // Triplets count back from the newest byte of the window:
+        // A  - B   - C  - D  - E
+        // 4    3     2    1    0
+        for (idx, &(salt, x, y, z)) in triplets.iter().enumerate() {
+            let (x, y, z) = (
+                window_byte(window, x),
+                window_byte(window, y),
+                window_byte(window, z),
+            );
+            bucket(self.triplet(idx, salt, x, y, z));
+        }
+    }
+}
+
+/// Any [`TripletHasher`], used as is.
+pub(crate) struct Plain<'a, K>(pub(crate) &'a K);
+
+impl<K: TripletHasher> TripletMixer for Plain<'_, K> {
+    #[inline(always)]
+    fn triplet(&self, _: usize, salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
+        self.0.hash(salt, ii, jj, kk)
+    }
+
+    #[inline(always)]
+    fn hash(&self, salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
+        self.0.hash(salt, ii, jj, kk)
+    }
+}
+
+/// Pearson hashing with the first two lookups of each salt of the selection precomputed, saving
+/// one of four dependent lookups per hash.
+pub(crate) struct PearsonTables {
+    table: [u8; 256],
+    /// ```salted[idx][ii]``` is the state after hashing ```ii``` with the salt of triplet ```idx```.
+    salted: [[u8; 256]; PearsonTables::MAX_TRIPLETS],
+    zero: [u8; 256],
+}
+
+impl PearsonTables {
+    /// Number of input bytes from which building the tables pays off.
+    pub(crate) const MIN_LEN: usize = 1 << 12;
+    /// Largest supported selection, that of the largest window.
+    pub(crate) const MAX_TRIPLETS: usize = 21;
+
+    pub(crate) fn new(pearson: &Pearson, triplets: &[(u8, usize, usize, usize)]) -> Self {
+        let table = pearson.table;
+        let salt_table =
+            |salt: u8| std::array::from_fn(|ii| table[(table[salt as usize] ^ ii as u8) as usize]);
+
+        let mut salted = [[0; 256]; Self::MAX_TRIPLETS];
+        for (row, &(salt, ..)) in salted.iter_mut().zip(triplets) {
+            *row = salt_table(salt);
+        }
+
+        Self {
+            table,
+            salted,
+            zero: salt_table(0),
+        }
     }
 }
 
+impl TripletMixer for PearsonTables {
+    #[inline(always)]
+    fn triplet(&self, idx: usize, _: u8, ii: u8, jj: u8, kk: u8) -> u8 {
+        let h = self.table[(self.salted[idx][ii as usize] ^ jj) as usize];
+        self.table[(h ^ kk) as usize]
+    }
+
+    #[inline(always)]
+    fn hash(&self, salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
+        let mut h = self.table[salt as usize];
+        h = self.table[(h ^ ii) as usize];
+        h = self.table[(h ^ jj) as usize];
+        self.table[(h ^ kk) as usize]
+    }
+
+    #[inline(always)]
+    fn first_checksum(&self, ii: u8, jj: u8, kk: u8) -> u8 {
+        let h = self.table[(self.zero[ii as usize] ^ jj) as usize];
+        self.table[(h ^ kk) as usize]
+    }
+}
+
+/// xxh3 of the four bytes of a triplet, inlined so that it costs a few multiplications instead of
+/// a call.
+impl TripletMixer for Xxh3 {
+    #[inline(always)]
+    fn triplet(&self, _: usize, salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
+        xxh3_short(&[salt, ii, jj, kk], self.seed) as u8
+    }
+
+    #[inline(always)]
+    fn hash(&self, salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
+        xxh3_short(&[salt, ii, jj, kk], self.seed) as u8
+    }
+}
+
+/// The X2 hashing of whole windows, see [`Xxh3::wide`].
+pub(crate) struct WideXxh3 {
+    seed: u64,
+    window_size: usize,
+}
+
+impl WideXxh3 {
+    /// Number of bucket indices taken from each hash, the lowest byte feeding the checksum.
+    const INDICES: usize = 7;
+
+    pub(crate) fn new(hasher: &Xxh3, window_size: usize) -> Self {
+        Self {
+            seed: hasher.seed,
+            window_size,
+        }
+    }
+
+    /// Hashes the window in input order. Selections of more than seven triplets take their
+    /// further indices from hashes with the next seeds.
+    #[inline(always)]
+    fn hash(&self, window: u64, group: usize) -> u64 {
+        let bytes = (window << (8 * (8 - self.window_size))).to_be_bytes();
+        xxh3_short(
+            &bytes[..self.window_size],
+            self.seed.wrapping_add(group as u64),
+        )
+    }
+}
+
+impl Mixer for WideXxh3 {
+    #[inline(always)]
+    fn checksum(&self, window: u64, checksum: &mut [u8]) {
+        self.window(window, &[], checksum, |_| ());
+    }
+
+    #[inline(always)]
+    fn buckets(&self, window: u64, triplets: &[(u8, usize, usize, usize)], bucket: impl FnMut(u8)) {
+        self.window(window, triplets, &mut [], bucket);
+    }
+
+    #[inline(always)]
+    fn window(
+        &self,
+        window: u64,
+        triplets: &[(u8, usize, usize, usize)],
+        checksum: &mut [u8],
+        mut bucket: impl FnMut(u8),
+    ) {
+        let mut h = self.hash(window, 0);
+
+        // Each checksum byte is chained to the previous one, as with triplet hashing.
+        let mut salt = 0;
+        for item in checksum.iter_mut() {
+            *item = pearson_h2(salt, h as u8, *item);
+            salt = *item;
+        }
+
+        for idx in 0..triplets.len() {
+            let group = idx / Self::INDICES;
+            if group > 0 && idx % Self::INDICES == 0 {
+                h = self.hash(window, group);
+            }
+            bucket((h >> (8 * (idx % Self::INDICES + 1))) as u8);
+        }
+    }
+}
+
+/// Computes ```xxh3_64_with_seed``` of four to eight bytes, following the short input path of
+/// xxh3 with its default secret.
+#[inline(always)]
+fn xxh3_short(input: &[u8], seed: u64) -> u64 {
+    // Bytes 8 to 24 of the default secret, as little-endian integers.
+    const SECRET: (u64, u64) = (0x1CAD_21F7_2C81_017C, 0xDB97_9083_E96D_D4DE);
+    const PRIME: u64 = 0x9FB2_1C65_1E98_DF25;
+
+    let len = input.len();
+    let seed = seed ^ ((seed as u32).swap_bytes() as u64) << 32;
+    let bitflip = (SECRET.0 ^ SECRET.1).wrapping_sub(seed);
+    let first = u32::from_le_bytes(input[..4].try_into().unwrap()) as u64;
+    let last = u32::from_le_bytes(input[(len - 4)..].try_into().unwrap()) as u64;
+
+    let mut h = last.wrapping_add(first << 32) ^ bitflip;
+    h ^= h.rotate_left(49) ^ h.rotate_left(24);
+    h = h.wrapping_mul(PRIME);
+    h ^= (h >> 35) + len as u64;
+    h = h.wrapping_mul(PRIME);
+
+    h ^ (h >> 28)
+}
+
+/// Derives the fingerprint of a key, independently of the table or seed derived from it.
+fn key_fingerprint(key: &[u8]) -> u16 {
+    xxhash_rust::xxh3::xxh3_64_with_seed(key, u64::from_le_bytes(*b"TxLshKey")) as u16
+}
+
 /// Exposed to Python as pearson_hash
 pub(crate) fn pearson_h(salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
     let mut h = V_TABLE[salt as usize];
@@ -19,6 +469,12 @@ pub(crate) fn pearson_h(salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
     V_TABLE[(h ^ kk) as usize]
 }
 
+/// Pearson hashing of two bytes with a salt.
+pub(crate) fn pearson_h2(salt: u8, ii: u8, jj: u8) -> u8 {
+    let h = V_TABLE[(V_TABLE[salt as usize] ^ ii) as usize];
+    V_TABLE[(h ^ jj) as usize]
+}
+
 /// same interface as pearson_h
 /// invokes xxh3_64 and truncates into u8
 pub(crate) fn xxhash_h(salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
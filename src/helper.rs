@@ -1,17 +1,71 @@
-use crate::consts::TOPVAL;
+use crate::consts::{TOPVAL, TOPVAL_EXTENDED, TOPVAL_SHORT};
 use crate::error::TxLshError;
+use crate::tag::Tag;
 
 use std::cmp::Ordering::{Equal, Greater, Less};
 use std::ops::{Add, Sub};
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 
 pub(crate) const BUCKET_SIZE: usize = 256;
-/// Size of a sliding window to process a byte string and populate an array of bucket counts.
-pub(crate) const WINDOW_SIZE: usize = 5;
+/// Size of the largest supported sliding window.
+pub(crate) const MAX_WINDOW_SIZE: usize = 8;
 
-static mut BIT_PAIRS_FLAG: bool = false;
-static mut BIT_PAIRS_DIFF: [[usize; 256]; 256] = [[0; 256]; 256];
+/// Triplets hashed into buckets for each window position, as `(salt, x, y, z)` where `x`, `y` and
+/// `z` count back from the newest byte of the window.
+///
+/// A window of size `n` uses every triplet whose positions are all below `n`. The first six are
+/// the triplets of the reference window of five bytes:
+/// ```text
+/// A  - B   - C  - D  - E  - F  - G  - H
+/// 0    1     2    3    4    5    6    7
+/// ```
+/// Larger windows pair the newest byte with each earlier byte and the new oldest one.
+const TRIPLETS: [(u8, usize, usize, usize); 21] = [
+    (2, 0, 1, 2),
+    (3, 0, 1, 3),
+    (5, 0, 2, 3),
+    (7, 0, 2, 4),
+    (11, 0, 1, 4),
+    (13, 0, 3, 4),
+    (17, 0, 1, 5),
+    (19, 0, 2, 5),
+    (23, 0, 3, 5),
+    (29, 0, 4, 5),
+    (31, 0, 1, 6),
+    (37, 0, 2, 6),
+    (41, 0, 3, 6),
+    (43, 0, 4, 6),
+    (47, 0, 5, 6),
+    (53, 0, 1, 7),
+    (59, 0, 2, 7),
+    (61, 0, 3, 7),
+    (67, 0, 4, 7),
+    (71, 0, 5, 7),
+    (73, 0, 6, 7),
+];
 
-/// enums
+/// Distance between two 3-bit codes. Codes at opposite ends are penalised twice.
+const OCTILE_DIFF: [[usize; 8]; 8] = level_diff_table();
+/// Distance between two 4-bit codes. Codes at opposite ends are penalised twice.
+const HEXADECILE_DIFF: [[usize; 16]; 16] = level_diff_table();
+
+/// Letters that cannot start a custom version prefix: those of built-in versions and those
+/// marking digest settings.
+const RESERVED_LETTERS: &str = "TXWSQLKRP";
+
+/// Largest number of prefixes that can be registered with [`Version::custom`].
+const MAX_CUSTOM_VERSIONS: usize = 32;
+
+/// Prefixes registered with [`Version::custom`]. Slots are filled in order and never cleared, so
+/// parsing reads them without locking.
+static CUSTOM_VERSIONS: [OnceLock<&'static str>; MAX_CUSTOM_VERSIONS] =
+    [const { OnceLock::new() }; MAX_CUSTOM_VERSIONS];
+/// Serialises registrations with [`Version::custom`].
+static CUSTOM_VERSIONS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Distance between two bytes of four 2-bit quartile codes each.
+static BIT_PAIRS_DIFF: [[u8; 256]; 256] = bit_pairs_table();
 
 /// An enum determining the number of buckets for hashing.
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
@@ -50,8 +104,384 @@ impl ChecksumKind {
     }
 }
 
+/// An enum determining the size of the sliding window.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub enum WindowKind {
+    /// Window of 4 bytes, hashing 3 triplets per byte.
+    Window4,
+    /// Window of 5 bytes, hashing 6 triplets per byte. Used by the reference implementation.
+    #[default]
+    Window5,
+    /// Window of 6 bytes, hashing 10 triplets per byte.
+    Window6,
+    /// Window of 7 bytes, hashing 15 triplets per byte.
+    Window7,
+    /// Window of 8 bytes, hashing 21 triplets per byte.
+    Window8,
+}
+
+impl WindowKind {
+    /// Returns the number of bytes in the window.
+    pub fn window_size(&self) -> usize {
+        match self {
+            WindowKind::Window4 => 4,
+            WindowKind::Window5 => 5,
+            WindowKind::Window6 => 6,
+            WindowKind::Window7 => 7,
+            WindowKind::Window8 => 8,
+        }
+    }
+
+    pub(crate) fn from_window_size(size: usize) -> Option<Self> {
+        match size {
+            4 => Some(WindowKind::Window4),
+            5 => Some(WindowKind::Window5),
+            6 => Some(WindowKind::Window6),
+            7 => Some(WindowKind::Window7),
+            8 => Some(WindowKind::Window8),
+            _ => None,
+        }
+    }
+
+    /// Returns the triplets hashed for each window position.
+    pub(crate) fn triplets(&self) -> &'static [(u8, usize, usize, usize)] {
+        match self {
+            WindowKind::Window4 => &TRIPLETS[..3],
+            WindowKind::Window5 => &TRIPLETS[..6],
+            WindowKind::Window6 => &TRIPLETS[..10],
+            WindowKind::Window7 => &TRIPLETS[..15],
+            WindowKind::Window8 => &TRIPLETS[..],
+        }
+    }
+}
+
+/// The triplets of window positions hashed into buckets for every byte of input.
+///
+/// Each triplet is a salt followed by three positions in the sliding window, counting back from
+/// the newest byte. The default selection of each [`WindowKind`] reproduces the reference digests;
+/// any other selection is recorded in the hash string by a fingerprint, e.g. ```"T1S1A2B..."```.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct TripletSelection {
+    triplets: Vec<(u8, usize, usize, usize)>,
+}
+
+impl TripletSelection {
+    /// Constructs a selection from a list of ```(salt, x, y, z)``` triplets.
+    ///
+    /// Fails if the list is empty or a position lies outside of the largest window.
+    pub fn new(triplets: &[(u8, usize, usize, usize)]) -> Result<Self, TxLshError> {
+        let selection = Self {
+            triplets: triplets.to_vec(),
+        };
+
+        if triplets.is_empty() || selection.window_size() > MAX_WINDOW_SIZE {
+            Err(TxLshError::InvalidTriplets)?
+        }
+
+        Ok(selection)
+    }
+
+    /// Returns the triplets hashed by default with a given window.
+    pub fn default_for(window: WindowKind) -> Self {
+        Self {
+            triplets: window.triplets().to_vec(),
+        }
+    }
+
+    /// Returns the ```(salt, x, y, z)``` triplets of the selection.
+    pub fn triplets(&self) -> &[(u8, usize, usize, usize)] {
+        &self.triplets
+    }
+
+    /// Returns the smallest window size covering every position of the selection.
+    pub fn window_size(&self) -> usize {
+        self.triplets
+            .iter()
+            .map(|&(_, x, y, z)| x.max(y).max(z) + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn fingerprint(&self) -> u16 {
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        for &(salt, x, y, z) in &self.triplets {
+            hasher.update(&[salt, x as u8, y as u8, z as u8]);
+        }
+
+        hasher.digest() as u16
+    }
+}
+
+/// An enum determining how many quantiles each bucket count is encoded against.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub enum QuantileKind {
+    /// Buckets are split by quartiles into 2-bit codes, as in the reference implementation.
+    #[default]
+    Quartile,
+    /// Buckets are split by octiles into 3-bit codes.
+    Octile,
+    /// Buckets are split by sixteenths into 4-bit codes.
+    Hexadecile,
+}
+
+impl QuantileKind {
+    /// Returns the number of quantiles, i.e. the number of distinct codes per bucket.
+    pub fn quantile_count(&self) -> usize {
+        1 << self.code_bits()
+    }
+
+    /// Returns the number of bits used to encode a bucket.
+    pub fn code_bits(&self) -> usize {
+        match self {
+            QuantileKind::Quartile => 2,
+            QuantileKind::Octile => 3,
+            QuantileKind::Hexadecile => 4,
+        }
+    }
+
+    pub(crate) fn from_code_bits(bits: usize) -> Option<Self> {
+        match bits {
+            2 => Some(QuantileKind::Quartile),
+            3 => Some(QuantileKind::Octile),
+            4 => Some(QuantileKind::Hexadecile),
+            _ => None,
+        }
+    }
+}
+
+/// An enum determining how the input length is captured in a digest.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub enum LengthKind {
+    /// One byte length code covering inputs of up to 4GB, as in the reference implementation.
+    #[default]
+    Standard,
+    /// Two byte length code covering inputs of any size.
+    Extended,
+    /// One byte length code with 5% steps below 64KB, covering inputs of up to 4GB.
+    ShortText,
+}
+
+impl LengthKind {
+    /// Returns the number of bytes of the length code.
+    pub fn len_bytes(&self) -> usize {
+        match self {
+            LengthKind::Standard | LengthKind::ShortText => 1,
+            LengthKind::Extended => 2,
+        }
+    }
+
+    pub(crate) fn id(&self) -> usize {
+        match self {
+            LengthKind::Standard => 0,
+            LengthKind::Extended => 1,
+            LengthKind::ShortText => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: usize) -> Option<Self> {
+        match id {
+            0 => Some(LengthKind::Standard),
+            1 => Some(LengthKind::Extended),
+            2 => Some(LengthKind::ShortText),
+            _ => None,
+        }
+    }
+
+    /// Returns the upper bounds of the input lengths captured by each length code.
+    pub(crate) fn table(&self) -> &'static [u64] {
+        match self {
+            LengthKind::Standard => &TOPVAL,
+            LengthKind::Extended => &TOPVAL_EXTENDED,
+            LengthKind::ShortText => &TOPVAL_SHORT,
+        }
+    }
+
+    /// Returns the penalty between the length codes ```x``` and ```y```.
+    ///
+    /// Standard and extended codes wrap around as in the reference implementation. Short text
+    /// codes do not fill their byte, so they are compared without wrapping, or the shortest and
+    /// longest codes would be close. They are also about twice as fine as the standard ones above
+    /// 3KB, so each step weighs half as much and a difference of two steps is still tolerated.
+    pub(crate) fn len_distance(&self, x: usize, y: usize) -> usize {
+        match self {
+            LengthKind::Standard | LengthKind::Extended => {
+                match mod_diff(x, y, 1 << (self.len_bytes() << 3)) {
+                    x @ 0..=1 => x,
+                    x => x * 12,
+                }
+            }
+            LengthKind::ShortText => match x.abs_diff(y) {
+                x @ 0..=2 => x,
+                x => x * 6,
+            },
+        }
+    }
+}
+
+/// An enum determining which inputs are long and varied enough to be hashed.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub enum MinSizePolicy {
+    /// Requires an input of at least 50 bytes.
+    #[default]
+    Standard,
+    /// Requires an input of at least 256 bytes filling more than half of the buckets, as the
+    /// conservative mode of the reference implementation.
+    Conservative,
+    /// Hashes inputs of any length, as long as the third quartile is not zero.
+    Force,
+    /// Requires an input of at least ```min_len``` bytes filling at least ```min_buckets``` buckets.
+    Custom { min_len: usize, min_buckets: usize },
+}
+
+impl MinSizePolicy {
+    /// Returns the minimal input length in bytes.
+    pub fn min_len(&self) -> usize {
+        match self {
+            MinSizePolicy::Standard => 50,
+            MinSizePolicy::Conservative => 256,
+            MinSizePolicy::Force => 0,
+            MinSizePolicy::Custom { min_len, .. } => *min_len,
+        }
+    }
+
+    /// Returns the minimal number of non-empty buckets out of ```bucket_count```.
+    pub fn min_buckets(&self, bucket_count: usize) -> usize {
+        match self {
+            MinSizePolicy::Standard | MinSizePolicy::Force => 0,
+            MinSizePolicy::Conservative => (bucket_count >> 1) + 1,
+            MinSizePolicy::Custom { min_buckets, .. } => *min_buckets,
+        }
+    }
+}
+
+/// An enum determining how runs of a repeated byte contribute to the buckets, so that padding
+/// does not dominate the digest.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub enum RunFilter {
+    /// Processes every byte.
+    #[default]
+    Off,
+    /// Processes at most the first ```n``` bytes of each run.
+    Cap(u16),
+    /// Drops every run longer than ```n``` bytes.
+    Skip(u16),
+}
+
+impl RunFilter {
+    pub(crate) fn id(&self) -> usize {
+        match self {
+            RunFilter::Off => 0,
+            RunFilter::Cap(_) => 1,
+            RunFilter::Skip(_) => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: usize, n: u16) -> Option<Self> {
+        match id {
+            1 => Some(RunFilter::Cap(n)),
+            2 => Some(RunFilter::Skip(n)),
+            _ => None,
+        }
+    }
+
+    /// Returns the longest run processed in full.
+    pub(crate) fn max_run(&self) -> u16 {
+        match self {
+            RunFilter::Off => u16::MAX,
+            RunFilter::Cap(n) | RunFilter::Skip(n) => *n,
+        }
+    }
+}
+
+/// Parameters of sampled hashing, reading an evenly spread subset of the blocks of an input.
+///
+/// The input is split into groups of ```stride``` blocks, and one block chosen from the seed is
+/// read in each group.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct Sampling {
+    block_bits: u8,
+    stride: u16,
+    seed: u16,
+}
+
+impl Sampling {
+    /// Constructs sampling parameters reading about ```ratio``` of an input in blocks of
+    /// ```block_size``` bytes.
+    ///
+    /// Fails with [`TxLshError::InvalidSampling`] unless ```block_size``` is a power of two and
+    /// ```ratio``` lies between 1/65535 and 1.
+    pub fn new(block_size: usize, ratio: f64, seed: u16) -> Result<Self, TxLshError> {
+        let stride = (1. / ratio).round();
+        let valid = ratio > 0. && ratio <= 1. && stride <= u16::MAX as f64;
+
+        if !block_size.is_power_of_two() || !valid {
+            Err(TxLshError::InvalidSampling)?
+        }
+
+        Ok(Self {
+            block_bits: block_size.trailing_zeros() as u8,
+            stride: stride as u16,
+            seed,
+        })
+    }
+
+    pub(crate) fn from_parts(block_bits: u8, stride: u16, seed: u16) -> Option<Self> {
+        (block_bits < 64 && stride > 0).then_some(Self {
+            block_bits,
+            stride,
+            seed,
+        })
+    }
+
+    pub(crate) fn parts(&self) -> (u8, u16, u16) {
+        (self.block_bits, self.stride, self.seed)
+    }
+
+    /// Returns the size of a block in bytes.
+    pub fn block_size(&self) -> u64 {
+        1 << self.block_bits
+    }
+
+    /// Returns the offsets of the blocks sampled from an input of ```len``` bytes, in order.
+    pub(crate) fn block_offsets(&self, len: u64) -> impl Iterator<Item = u64> + '_ {
+        let group_size = self.block_size() * self.stride as u64;
+
+        (0..len.div_ceil(group_size))
+            .map(move |group| {
+                // splitmix64 of the group index, keyed by the seed.
+                let mut z = (group ^ ((self.seed as u64) << 48)).wrapping_add(0x9E3779B97F4A7C15);
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+                z ^= z >> 31;
+
+                group * group_size + (z % self.stride as u64) * self.block_size()
+            })
+            .filter(move |&offset| offset < len)
+    }
+}
+
+/// A flag stopping a running hash, shared between the thread hashing and the ones cancelling it.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests every hash checking this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Relaxed)
+    }
+}
+
 /// An enum representing the version of TxLsh.
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum Version {
     /// Original Tlsh, mapping to an empty string ```""```.
     Original,
@@ -59,117 +489,231 @@ pub enum Version {
     Version4,
     /// Original TxLsh, mapping to an string ```"X1"```.
     TxLshV1,
+    /// TxLsh hashing each whole window once, mapping to an string ```"X2"```.
+    TxLshV2,
+    /// User-defined version registered with [`Version::custom`], mapping to its prefix. It names
+    /// no hash function of its own: builders must hash with a
+    /// [`TripletHasher`](crate::TripletHasher) returning it from its ```version```.
+    Custom(CustomVersion),
 }
 
+/// A prefix registered with [`Version::custom`], which is the only way to construct one.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct CustomVersion(&'static str);
+
 impl Version {
     pub fn ver(&self) -> &str {
         match self {
             Version::Original => "",
             Version::Version4 => "T1",
             Version::TxLshV1 => "X1",
+            Version::TxLshV2 => "X2",
+            Version::Custom(custom) => custom.0,
+        }
+    }
+
+    /// Registers a prefix for digests of a user-defined [`TripletHasher`](crate::TripletHasher),
+    /// so that they can be parsed back with [`TxLsh::from_str`](crate::TxLsh).
+    ///
+    /// A prefix is an uppercase letter followed by one to three digits, e.g. ```"Z1"```. The letter
+    /// can be neither a hex digit nor a letter used by built-in versions or digest settings.
+    /// Fails if the prefix is malformed, overlaps with another registered prefix, or if 32
+    /// prefixes are already registered.
+    ///
+    /// The returned version only labels digests. Building with it as the hasher of a
+    /// [`TxLshBuilder`](crate::TxLshBuilder) fails with [`TxLshError::UnboundVersion`].
+    pub fn custom(prefix: &'static str) -> Result<Version, TxLshError> {
+        let mut chars = prefix.chars();
+        let valid = matches!(chars.next(), Some(c) if c.is_ascii_uppercase()
+                && !c.is_ascii_hexdigit()
+                && !RESERVED_LETTERS.contains(c))
+            && (2..=4).contains(&prefix.len())
+            && chars.all(|c| c.is_ascii_digit());
+
+        let _guard = CUSTOM_VERSIONS_LOCK
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let overlaps = custom_versions()
+            .any(|v| v != prefix && (v.starts_with(prefix) || prefix.starts_with(v)));
+
+        if !valid || overlaps {
+            Err(TxLshError::InvalidVersion)?
         }
+
+        if !custom_versions().any(|v| v == prefix) {
+            CUSTOM_VERSIONS
+                .iter()
+                .find(|slot| slot.get().is_none())
+                .ok_or(TxLshError::InvalidVersion)?
+                .set(prefix)
+                .ok();
+        }
+
+        Ok(Version::Custom(CustomVersion(prefix)))
+    }
+
+    /// Returns the version whose prefix starts ```s```. Registered prefixes never overlap each
+    /// other or the built-in ones, so only the empty prefix of [`Version::Original`] needs to be
+    /// tried last.
+    pub(crate) fn from_prefix(s: &str) -> Version {
+        Self::known()
+            .find(|v| s.starts_with(v.ver()))
+            .unwrap_or(Version::Original)
+    }
+
+    /// Returns the version whose prefix is exactly ```ver```.
+    pub(crate) fn from_ver(ver: &str) -> Option<Version> {
+        Self::known()
+            .chain([Version::Original])
+            .find(|v| v.ver() == ver)
+    }
+
+    /// Iterates over every version but [`Version::Original`].
+    fn known() -> impl Iterator<Item = Version> {
+        [Version::Version4, Version::TxLshV1, Version::TxLshV2]
+            .into_iter()
+            .chain(custom_versions().map(|prefix| Version::Custom(CustomVersion(prefix))))
     }
 }
 
-pub(crate) fn hash_len(bucket: BucketKind, checksum: ChecksumKind, ver: Version) -> usize {
-    (bucket.bucket_count() >> 1) + (checksum.checksum_len() << 1) + ver.ver().len() + 4
+/// Iterates over the prefixes registered with [`Version::custom`].
+fn custom_versions() -> impl Iterator<Item = &'static str> {
+    CUSTOM_VERSIONS.iter().map_while(|slot| slot.get().copied())
 }
 
-pub(crate) fn find_quartiles(buckets: &[u32], bucket_count: usize) -> (u32, u32, u32) {
-    let mut buckets_copy: Vec<u32> = buckets[0..bucket_count].to_vec();
-    let (mut shortcut_low, mut shortcut_high) = (vec![0; bucket_count], vec![0; bucket_count]);
-    let (mut spl, mut sph) = (0, 0);
+pub(crate) fn hash_len(
+    bucket: BucketKind,
+    checksum: ChecksumKind,
+    ver: Version,
+    tag: &Tag,
+) -> usize {
+    (code_len(bucket, tag.quantile_kind) << 1)
+        + (checksum.checksum_len() << 1)
+        + ver.ver().len()
+        + tag.len()
+        + (tag.length_kind.len_bytes() << 1)
+        + 2
+}
+
+/// Returns the number of bytes holding the codes of all buckets.
+pub(crate) fn code_len(bucket: BucketKind, quantile: QuantileKind) -> usize {
+    (bucket.bucket_count() * quantile.code_bits()) >> 3
+}
+
+/// Packs the code of each bucket, i.e. the number of thresholds below its count, into a bit
+/// stream of ```bits``` per bucket. The first bucket takes the lowest bits of the first byte.
+pub(crate) fn encode_codes(buckets: &[u64], thresholds: &[u64], bits: usize, codes: &mut Vec<u8>) {
+    codes.clear();
+    codes.resize((buckets.len() * bits) >> 3, 0);
+
+    for (ii, count) in buckets.iter().enumerate() {
+        let code = thresholds.iter().filter(|&q| q < count).count();
+        let pos = ii * bits;
+        let value = (code << (pos & 7)) as u16;
+
+        codes[pos >> 3] |= value as u8;
+        if (pos & 7) + bits > 8 {
+            codes[(pos >> 3) + 1] |= (value >> 8) as u8;
+        }
+    }
+}
+
+fn decode_code(codes: &[u8], idx: usize, bits: usize) -> usize {
+    let pos = idx * bits;
+    let mut value = codes[pos >> 3] as usize;
+    if (pos & 7) + bits > 8 {
+        value |= (codes[(pos >> 3) + 1] as usize) << 8;
+    }
+
+    (value >> (pos & 7)) & ((1 << bits) - 1)
+}
+
+/// Calculates the distance between the codes of two digests of the same quantile kind.
+pub(crate) fn code_distance(x: &[u8], y: &[u8], quantile: QuantileKind) -> usize {
+    match quantile {
+        QuantileKind::Quartile => bit_distance(x, y),
+        QuantileKind::Octile => level_distance(x, y, &OCTILE_DIFF),
+        QuantileKind::Hexadecile => level_distance(x, y, &HEXADECILE_DIFF),
+    }
+}
+
+fn level_distance<const N: usize>(x: &[u8], y: &[u8], table: &[[usize; N]; N]) -> usize {
+    let bits = N.trailing_zeros() as usize;
+
+    (0..((x.len() << 3) / bits))
+        .map(|ii| table[decode_code(x, ii, bits)][decode_code(y, ii, bits)])
+        .sum()
+}
+
+const fn level_diff_table<const N: usize>() -> [[usize; N]; N] {
+    let mut table = [[0; N]; N];
+    let mut ii = 0;
+
+    while ii < N {
+        let mut jj = 0;
+        while jj < N {
+            let d = ii.abs_diff(jj);
+            table[ii][jj] = if d == N - 1 { d * 2 } else { d };
+            jj += 1;
+        }
+        ii += 1;
+    }
+
+    table
+}
+
+/// Finds the bucket counts splitting the first ```bucket_count``` buckets into quartiles, in a
+/// stack buffer.
+///
+/// The median is selected with the quickselect of the original implementation, whose pivots bound
+/// the searches of the other quartiles. As there, a quartile is left at zero when no pivot fell
+/// between it and the median, which existing digests depend on.
+pub(crate) fn find_quartiles(buckets: &[u64], bucket_count: usize) -> (u64, u64, u64) {
+    let mut copy = [0; BUCKET_SIZE];
+    let copy = &mut copy[..bucket_count];
+    copy.copy_from_slice(&buckets[..bucket_count]);
 
     let quartile = bucket_count >> 2;
     let p1 = quartile - 1;
     let p2 = p1 + quartile;
     let p3 = p2 + quartile;
-    let end = p3 + quartile;
 
-    // Applies quicksort to find p2
-    let (mut low, mut high) = (0, end);
-    let q2 = loop {
-        let pivot = partition(&mut buckets_copy, low, high);
+    // The highest pivot below p2 and the lowest above it.
+    let (mut pivot_low, mut pivot_high) = (None, None);
+    let (mut low, mut high) = (0, bucket_count - 1);
+    loop {
+        let pivot = partition(copy, low, high);
 
         match pivot.cmp(&p2) {
             Greater => {
                 high = pivot - 1;
-                shortcut_high[sph] = pivot;
-                sph += 1;
+                pivot_high = Some(pivot);
             }
             Less => {
                 low = pivot + 1;
-                shortcut_low[spl] = pivot;
-                spl += 1;
-            }
-
-            Equal => {
-                break buckets_copy[p2];
-            }
-        }
-    };
-
-    shortcut_low[spl] = p2 - 1;
-    shortcut_high[sph] = p2 + 1;
-
-    let mut q1 = 0;
-    low = 0;
-    //for ii in 0..spl {
-    for item in shortcut_low.iter().take(spl) {
-        high = *item;
-
-        match high.cmp(&p1) {
-            Greater => {
-                q1 = loop {
-                    let pivot = partition(&mut buckets_copy, low, high);
-                    match pivot.cmp(&p1) {
-                        Greater => high = pivot - 1,
-                        Less => low = pivot + 1,
-                        Equal => break buckets_copy[p1],
-                    }
-                };
-                break;
-            }
-            Less => {
-                low = high;
-            }
-            Equal => {
-                q1 = buckets_copy[p1];
-                break;
+                pivot_low = Some(pivot);
             }
+            Equal => break,
         }
     }
 
-    let mut q3 = 0;
-    high = end;
-    for item in shortcut_high.iter().take(sph) {
-        low = *item;
-        match low.cmp(&p3) {
-            Less => {
-                q3 = loop {
-                    let pivot = partition(&mut buckets_copy, low, high);
-                    match pivot.cmp(&p3) {
-                        Less => low = pivot + 1,
-                        Greater => high = pivot - 1,
-                        Equal => break buckets_copy[p3],
-                    }
-                };
-                break;
-            }
-
-            Equal => {
-                q3 = buckets_copy[p3];
-                break;
-            }
-
-            Greater => high = low,
-        }
-    }
+    let q2 = copy[p2];
+    let (lower, upper) = copy.split_at_mut(p2 + 1);
+    let q1 = match pivot_low {
+        Some(pivot) if pivot >= p1 => *lower.select_nth_unstable(p1).1,
+        _ => 0,
+    };
+    let q3 = match pivot_high {
+        Some(pivot) if pivot <= p3 => *upper.select_nth_unstable(p3 - p2 - 1).1,
+        _ => 0,
+    };
 
     (q1, q2, q3)
 }
 
-pub(crate) fn partition(buckets: &mut [u32], low: usize, high: usize) -> usize {
+/// Moves the middle element of ```buckets[low..=high]``` to its sorted position, which is
+/// returned, with the smaller elements before it and the others after it.
+fn partition(buckets: &mut [u64], low: usize, high: usize) -> usize {
     if low == high {
         return low;
     }
@@ -199,20 +743,81 @@ pub(crate) fn partition(buckets: &mut [u32], low: usize, high: usize) -> usize {
     result
 }
 
-pub(crate) fn l_capturing(len: usize) -> Result<usize, TxLshError> {
-    let (mut top, mut bottom) = (TOPVAL.len(), 0);
+/// Largest bucket count, plus one, of the buckets whose quantiles are found by counting.
+const SMALL_COUNTS: usize = 64;
+
+/// Finds the ```thresholds.len()``` bucket counts splitting the first ```bucket_count``` buckets
+/// into ```thresholds.len() + 1``` quantiles, in a stack buffer.
+pub(crate) fn find_quantiles(buckets: &[u64], bucket_count: usize, thresholds: &mut [u64]) {
+    let counts = &buckets[..bucket_count];
+    let quantile = bucket_count / (thresholds.len() + 1);
+
+    // Short inputs only have small counts, whose ranks are found faster by counting them.
+    if counts.iter().all(|&count| count < SMALL_COUNTS as u64) {
+        let mut occurrences = [0u16; SMALL_COUNTS];
+        for &count in counts {
+            occurrences[count as usize] += 1;
+        }
+
+        let (mut next, mut rank) = (0, 0);
+        for (count, &n) in occurrences.iter().enumerate() {
+            rank += n as usize;
+            while next < thresholds.len() && (next + 1) * quantile - 1 < rank {
+                thresholds[next] = count as u64;
+                next += 1;
+            }
+        }
+
+        return;
+    }
+
+    let mut copy = [0; BUCKET_SIZE];
+    let copy = &mut copy[..bucket_count];
+    copy.copy_from_slice(counts);
+
+    select_thresholds(copy, thresholds, 0, quantile, 0);
+}
+
+/// Selects the counts of rank ```(first + ii + 1) * quantile - 1``` in the whole buckets into
+/// ```thresholds[ii]```, ```counts``` being the part of the buckets starting at rank ```offset```.
+///
+/// Selecting the middle threshold leaves the smaller counts before it and the larger ones after
+/// it, in which the lower and upper thresholds are selected.
+fn select_thresholds(
+    counts: &mut [u64],
+    thresholds: &mut [u64],
+    first: usize,
+    quantile: usize,
+    offset: usize,
+) {
+    if thresholds.is_empty() {
+        return;
+    }
+
+    let mid = thresholds.len() / 2;
+    let rank = (first + mid + 1) * quantile - 1;
+    let (low, &mut value, high) = counts.select_nth_unstable(rank - offset);
+    let (lower, upper) = thresholds.split_at_mut(mid);
+
+    upper[0] = value;
+    select_thresholds(low, lower, first, quantile, offset);
+    select_thresholds(high, &mut upper[1..], first + mid + 1, quantile, rank + 1);
+}
+
+pub(crate) fn l_capturing(len: u64, table: &[u64]) -> Result<usize, TxLshError> {
+    let (mut top, mut bottom) = (table.len(), 0);
     let mut idx = top >> 1;
 
-    while idx < TOPVAL.len() {
+    while idx < table.len() {
         if idx == 0 {
             return Ok(idx);
         }
 
-        if len <= TOPVAL[idx] && len > TOPVAL[idx - 1] {
+        if len <= table[idx] && len > table[idx - 1] {
             return Ok(idx);
         }
 
-        if len < TOPVAL[idx] {
+        if len < table[idx] {
             top = idx - 1;
         } else {
             bottom = idx + 1;
@@ -237,54 +842,367 @@ where
     std::cmp::min(dl, dr)
 }
 
-pub(crate) fn bit_distance(x: &[u8], y: &[u8]) -> usize {
-    let mut result = 0;
-
-    for ii in 0..x.len() {
-        unsafe {
-            result += bit_pairs_diff(x[ii] as usize, y[ii] as usize);
-        }
+/// Appends ```value``` to ```out``` as an LEB128 varint.
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
     }
 
-    result
+    out.push(value as u8);
+}
+
+/// A cursor over serialised data, failing with [`TxLshError::InvalidBytes`] past its end.
+pub(crate) struct ByteReader<'a> {
+    data: &'a [u8],
+    offset: usize,
 }
 
-#[inline]
-unsafe fn bit_pairs_diff(row: usize, col: usize) -> usize {
-    let f = |x: &mut i16, y: &mut i16, diff: &mut i16| {
-        let d = (*x % 4 - *y % 4).abs();
-        *diff += if d == 3 { 6 } else { d };
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
 
-        *x /= 4;
-        *y /= 4;
-    };
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], TxLshError> {
+        let result = self
+            .data
+            .get(self.offset..self.offset + len)
+            .ok_or(TxLshError::InvalidBytes)?;
+        self.offset += len;
 
-    if !BIT_PAIRS_FLAG {
-        for ii in 0..256i16 {
-            for jj in 0..256 {
-                let (mut x, mut y, mut diff) = (ii, jj, 0);
-                for _ in 0..4 {
-                    f(&mut x, &mut y, &mut diff);
-                }
+        Ok(result)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, TxLshError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64, TxLshError> {
+        let mut result = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            result |= ((byte & 0x7F) as u64)
+                .checked_shl(shift)
+                .filter(|v| v >> shift == (byte & 0x7F) as u64)
+                .ok_or(TxLshError::InvalidBytes)?;
 
-                BIT_PAIRS_DIFF[ii as usize][jj as usize] = diff as usize;
+            if byte < 0x80 {
+                return Ok(result);
             }
         }
-        BIT_PAIRS_FLAG = true;
+
+        Err(TxLshError::InvalidBytes)
     }
 
-    BIT_PAIRS_DIFF[row][col]
+    /// Returns whether all the data has been read.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.offset == self.data.len()
+    }
+}
+
+pub(crate) fn bit_distance(x: &[u8], y: &[u8]) -> usize {
+    x.iter()
+        .zip(y)
+        .map(|(&x, &y)| BIT_PAIRS_DIFF[x as usize][y as usize] as usize)
+        .sum()
 }
 
+const fn bit_pairs_table() -> [[u8; 256]; 256] {
+    let codes: [[usize; 4]; 4] = level_diff_table();
+    let mut table = [[0; 256]; 256];
+    let mut ii = 0;
+
+    while ii < 256 {
+        let mut jj = 0;
+        while jj < 256 {
+            let mut shift = 0;
+            while shift < 8 {
+                table[ii][jj] += codes[(ii >> shift) & 3][(jj >> shift) & 3] as u8;
+                shift += 2;
+            }
+            jj += 1;
+        }
+        ii += 1;
+    }
+
+    table
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::TxLsh;
+    use crate::{TxLsh, TxLshBuilder};
 
     use super::*;
+    use std::cmp::Ordering::{Equal, Greater, Less};
 
     static LOREM_0: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
-    fn diff_test(hash:TxLsh) -> bool {
-        true
+
+    fn lorem_hash(window: WindowKind) -> TxLsh {
+        let mut builder = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        )
+        .with_window(window);
+        builder.update(LOREM_0);
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn diff_test() {
+        let hash = lorem_hash(WindowKind::Window5);
+        assert_eq!(0, hash.diff(&hash, true));
+    }
+
+    #[test]
+    fn test_window_triplets() {
+        let windows = [
+            (WindowKind::Window4, 3),
+            (WindowKind::Window5, 6),
+            (WindowKind::Window6, 10),
+            (WindowKind::Window7, 15),
+            (WindowKind::Window8, 21),
+        ];
+
+        for (window, count) in windows {
+            let triplets = window.triplets();
+            assert_eq!(count, triplets.len());
+            assert!(triplets
+                .iter()
+                .all(|&(_, x, y, z)| x.max(y).max(z) < window.window_size()));
+        }
+    }
+
+    /// The quickselect formerly used by find_quartiles, allocating its buffers.
+    fn find_quartiles_quickselect(buckets: &[u64], bucket_count: usize) -> (u64, u64, u64) {
+        let mut buckets_copy: Vec<u64> = buckets[0..bucket_count].to_vec();
+        let (mut shortcut_low, mut shortcut_high) = (vec![0; bucket_count], vec![0; bucket_count]);
+        let (mut spl, mut sph) = (0, 0);
+
+        let quartile = bucket_count >> 2;
+        let p1 = quartile - 1;
+        let p2 = p1 + quartile;
+        let p3 = p2 + quartile;
+        let end = p3 + quartile;
+
+        // Applies quicksort to find p2
+        let (mut low, mut high) = (0, end);
+        let q2 = loop {
+            let pivot = partition(&mut buckets_copy, low, high);
+
+            match pivot.cmp(&p2) {
+                Greater => {
+                    high = pivot - 1;
+                    shortcut_high[sph] = pivot;
+                    sph += 1;
+                }
+                Less => {
+                    low = pivot + 1;
+                    shortcut_low[spl] = pivot;
+                    spl += 1;
+                }
+
+                Equal => {
+                    break buckets_copy[p2];
+                }
+            }
+        };
+
+        shortcut_low[spl] = p2 - 1;
+        shortcut_high[sph] = p2 + 1;
+
+        let mut q1 = 0;
+        low = 0;
+        for item in shortcut_low.iter().take(spl) {
+            high = *item;
+
+            match high.cmp(&p1) {
+                Greater => {
+                    q1 = loop {
+                        let pivot = partition(&mut buckets_copy, low, high);
+                        match pivot.cmp(&p1) {
+                            Greater => high = pivot - 1,
+                            Less => low = pivot + 1,
+                            Equal => break buckets_copy[p1],
+                        }
+                    };
+                    break;
+                }
+                Less => {
+                    low = high;
+                }
+                Equal => {
+                    q1 = buckets_copy[p1];
+                    break;
+                }
+            }
+        }
+
+        let mut q3 = 0;
+        high = end;
+        for item in shortcut_high.iter().take(sph) {
+            low = *item;
+            match low.cmp(&p3) {
+                Less => {
+                    q3 = loop {
+                        let pivot = partition(&mut buckets_copy, low, high);
+                        match pivot.cmp(&p3) {
+                            Less => low = pivot + 1,
+                            Greater => high = pivot - 1,
+                            Equal => break buckets_copy[p3],
+                        }
+                    };
+                    break;
+                }
+
+                Equal => {
+                    q3 = buckets_copy[p3];
+                    break;
+                }
+
+                Greater => high = low,
+            }
+        }
+
+        (q1, q2, q3)
+    }
+
+    #[test]
+    fn test_find_quartiles() {
+        let mut state = 1u64;
+        let mut zeros = 0;
+        for ii in 0..300 {
+            // Few distinct counts on some rounds, to exercise ties, and large counts on others.
+            let modulo = [3, 20, 1000][ii % 3];
+            let buckets: Vec<u64> = (0..BUCKET_SIZE)
+                .map(|_| {
+                    state = state
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    (state >> 33) % modulo
+                })
+                .collect();
+
+            for count in [128, 256] {
+                let quartiles = find_quartiles(&buckets, count);
+                assert_eq!(find_quartiles_quickselect(&buckets, count), quartiles);
+                zeros += (quartiles.0 == 0 && modulo == 1000) as usize;
+            }
+        }
+        // Some rounds leave q1 at zero although no count is, see test_find_quartiles_sides.
+        assert!(zeros > 0);
+    }
+
+    #[test]
+    fn test_find_quartiles_sides() {
+        // Ascending counts put the first pivot at p2, so no pivot bounds the other quartiles,
+        // which are left at zero and make the digest fail with NoValidHash.
+        let ascending: Vec<u64> = (0..128).collect();
+        assert_eq!((0, 63, 0), find_quartiles(&ascending, 128));
+        assert_eq!(
+            find_quartiles_quickselect(&ascending, 128),
+            find_quartiles(&ascending, 128)
+        );
+    }
+
+    #[test]
+    fn test_find_quantiles() {
+        let mut builder = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        builder.update(LOREM_0);
+        let buckets = &builder.buckets();
+
+        for count in [128, 256] {
+            let (q1, q2, q3) = find_quartiles(buckets, count);
+            let mut octiles = [0; 7];
+            find_quantiles(buckets, count, &mut octiles);
+            assert_eq!([q1, q2, q3], [octiles[1], octiles[3], octiles[5]]);
+        }
+
+        let mut state = 5u64;
+        for _ in 0..100 {
+            let buckets: Vec<u64> = (0..BUCKET_SIZE)
+                .map(|_| {
+                    state = state
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    (state >> 33) % 50
+                })
+                .collect();
+
+            for count in [128, 256] {
+                let mut sorted = buckets[..count].to_vec();
+                sorted.sort_unstable();
+
+                for kind in [QuantileKind::Octile, QuantileKind::Hexadecile] {
+                    let quantile = count / kind.quantile_count();
+                    let expected: Vec<_> = (1..kind.quantile_count())
+                        .map(|ii| sorted[ii * quantile - 1])
+                        .collect();
+                    let mut thresholds = vec![0; kind.quantile_count() - 1];
+                    find_quantiles(&buckets, count, &mut thresholds);
+                    assert_eq!(expected, thresholds);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_l_capturing() {
+        for kind in [LengthKind::Standard, LengthKind::Extended] {
+            assert_eq!(0, l_capturing(1, kind.table()).unwrap());
+            assert_eq!(11, l_capturing(100, kind.table()).unwrap());
+            assert_eq!(169, l_capturing(4224281216, kind.table()).unwrap());
+        }
+
+        assert!(l_capturing(4224281217, LengthKind::Standard.table()).is_err());
+        assert_eq!(
+            170,
+            l_capturing(4224281217, LengthKind::Extended.table()).unwrap()
+        );
+        assert_eq!(
+            TOPVAL_EXTENDED.len() - 1,
+            l_capturing(u64::MAX, LengthKind::Extended.table()).unwrap()
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_short_text_length() {
+        let table = LengthKind::ShortText.table();
+        assert!(table.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(table[table.len() - 1] > TOPVAL[TOPVAL.len() - 1]);
+
+        // Paragraphs of 50 to 2000 bytes spread over about 80 codes instead of 11.
+        let short = l_capturing(2000, table).unwrap() - l_capturing(50, table).unwrap();
+        let standard = l_capturing(2000, &TOPVAL).unwrap() - l_capturing(50, &TOPVAL).unwrap();
+        assert_eq!((81, 11), (short, standard));
+
+        // Short text codes do not wrap, unlike standard ones.
+        let last = table.len() - 1;
+        assert_eq!(last * 6, LengthKind::ShortText.len_distance(0, last));
+        assert_eq!(2, LengthKind::ShortText.len_distance(last, last - 2));
+        assert_eq!(1, LengthKind::Standard.len_distance(0, 255));
+    }
+
+    #[test]
+    fn test_code_distance() {
+        let mut codes = Vec::new();
+        encode_codes(
+            &[0, 1, 2, 3, 4, 5, 6, 7],
+            &[0, 1, 2, 3, 4, 5, 6],
+            3,
+            &mut codes,
+        );
+        let zeros = vec![0; codes.len()];
+        // Levels 1 to 6 count once, level 7 sits at the opposite end and counts twice.
+        assert_eq!(
+            1 + 2 + 3 + 4 + 5 + 6 + 14,
+            code_distance(&codes, &zeros, QuantileKind::Octile)
+        );
+        assert_eq!(0, code_distance(&codes, &codes, QuantileKind::Octile));
+    }
+}
@@ -0,0 +1,316 @@
+use crate::{
+    helper::BUCKET_SIZE,
+    helper::{encode_codes, find_quantiles, find_quartiles, l_capturing, write_varint},
+    helper::{BucketKind, ByteReader, ChecksumKind, MinSizePolicy, QuantileKind, Version},
+    tag::Tag,
+    TxLsh, TxLshError,
+};
+
+/// Leading bytes of a serialised histogram, followed by the format version.
+const MAGIC: &[u8] = b"TXH";
+const FORMAT_VERSION: u8 = 1;
+
+/// The full bucket histogram of an input, as obtained from
+/// [`TxLshBuilder::histogram`](crate::TxLshBuilder::histogram).
+///
+/// Unlike a [`TxLsh`], it keeps the raw count of every bucket, so that exact distances can be
+/// computed and digests of any [`BucketKind`] derived later without reading the input again.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TxLshHistogram {
+    pub(crate) buckets: [u64; BUCKET_SIZE],
+    pub(crate) checksum_kind: ChecksumKind,
+    pub(crate) checksum: Vec<u8>,
+    pub(crate) data_len: u64,
+    pub(crate) min_size: MinSizePolicy,
+    pub(crate) ver: Version,
+    pub(crate) tag: Tag,
+}
+
+impl TxLshHistogram {
+    /// Returns the count of every bucket.
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// Returns the number of bytes the histogram was built from.
+    pub fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    /// Quantises the first buckets of the histogram into a digest, as [`TxLshBuilder::build`]
+    /// does.
+    ///
+    /// [`TxLshBuilder::build`]: crate::TxLshBuilder::build
+    pub fn to_txlsh(&self, bucket_kind: BucketKind) -> Result<TxLsh, TxLshError> {
+        let mut digest = TxLsh {
+            bucket_kind,
+            checksum_kind: self.checksum_kind,
+            ver: self.ver,
+            tag: self.tag,
+            checksum: Vec::new(),
+            len: 0,
+            q1ratio: 0,
+            q2ratio: 0,
+            codes: Vec::new(),
+        };
+        self.to_txlsh_into(bucket_kind, &mut digest)?;
+
+        Ok(digest)
+    }
+
+    /// Quantises the first buckets of the histogram into ```digest```, reusing its storage.
+    ///
+    /// The digest is left untouched on failure.
+    pub(crate) fn to_txlsh_into(
+        &self,
+        bucket_kind: BucketKind,
+        digest: &mut TxLsh,
+    ) -> Result<(), TxLshError> {
+        let bucket_count = bucket_kind.bucket_count();
+
+        if self.data_len < self.min_size.min_len() as u64 {
+            Err(TxLshError::MinSizeNotReached)?
+        }
+
+        let (q1, q2, q3) = find_quartiles(&self.buckets, bucket_count);
+
+        if q3 == 0 {
+            Err(TxLshError::NoValidHash)?
+        }
+
+        let buckets = &self.buckets[..bucket_count];
+        let nonzero = buckets.iter().filter(|&&count| count > 0).count();
+        if nonzero < self.min_size.min_buckets(bucket_count) {
+            Err(TxLshError::MinBucketsNotReached)?
+        }
+
+        let len = l_capturing(self.data_len, self.tag.length_kind.table())?;
+
+        match self.tag.quantile_kind {
+            QuantileKind::Quartile => encode_codes(buckets, &[q1, q2, q3], 2, &mut digest.codes),
+            kind => {
+                let mut thresholds = [0; 15];
+                let thresholds = &mut thresholds[..kind.quantile_count() - 1];
+                find_quantiles(&self.buckets, bucket_count, thresholds);
+                encode_codes(buckets, thresholds, kind.code_bits(), &mut digest.codes)
+            }
+        }
+
+        digest.bucket_kind = bucket_kind;
+        digest.checksum_kind = self.checksum_kind;
+        digest.ver = self.ver;
+        digest.tag = self.tag;
+        digest.checksum.clear();
+        digest.checksum.extend_from_slice(&self.checksum);
+        digest.len = len;
+        digest.q1ratio = (((q1 as f64 * 100.) / (q3 as f64)) as usize) % 16;
+        digest.q2ratio = (((q2 as f64 * 100.) / (q3 as f64)) as usize) % 16;
+
+        Ok(())
+    }
+
+    /// Calculates the cosine distance between two histograms, from 0 for proportional counts to 1
+    /// for counts sharing no bucket.
+    ///
+    /// As for [`TxLsh::diff`], both histograms are assumed to be built with the same settings.
+    pub fn cosine_distance(&self, other: &TxLshHistogram) -> f64 {
+        let (mut dot, mut norm_x, mut norm_y) = (0., 0., 0.);
+
+        for (&x, &y) in self.buckets.iter().zip(&other.buckets) {
+            let (x, y) = (x as f64, y as f64);
+            dot += x * y;
+            norm_x += x * x;
+            norm_y += y * y;
+        }
+
+        match (norm_x == 0., norm_y == 0.) {
+            (true, true) => 0.,
+            (false, false) => (1. - dot / (norm_x * norm_y).sqrt()).max(0.),
+            _ => 1.,
+        }
+    }
+
+    /// Calculates the Jensen-Shannon distance between the normalised histograms, from 0 for
+    /// proportional counts to 1 for counts sharing no bucket.
+    pub fn jensen_shannon_distance(&self, other: &TxLshHistogram) -> f64 {
+        let total_x = self.buckets.iter().sum::<u64>() as f64;
+        let total_y = other.buckets.iter().sum::<u64>() as f64;
+
+        match (total_x == 0., total_y == 0.) {
+            (true, true) => return 0.,
+            (false, false) => (),
+            _ => return 1.,
+        }
+
+        // Terms of the Kullback-Leibler divergences against the mixture, in bits.
+        let kl = |p: f64, m: f64| if p > 0. { p * (p / m).log2() } else { 0. };
+        let mut divergence = 0.;
+
+        for (&x, &y) in self.buckets.iter().zip(&other.buckets) {
+            let (p, q) = (x as f64 / total_x, y as f64 / total_y);
+            let m = (p + q) / 2.;
+            divergence += (kl(p, m) + kl(q, m)) / 2.;
+        }
+
+        divergence.clamp(0., 1.).sqrt()
+    }
+
+    /// Calculates the sum of the absolute differences between the bucket counts.
+    pub fn l1_distance(&self, other: &TxLshHistogram) -> u64 {
+        self.buckets
+            .iter()
+            .zip(&other.buckets)
+            .map(|(&x, &y)| x.abs_diff(y))
+            .sum()
+    }
+
+    /// Serialises the histogram, with bucket counts and length as varints.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(BUCKET_SIZE + 32);
+        result.extend_from_slice(MAGIC);
+        result.push(FORMAT_VERSION);
+
+        let (ver, tag) = (self.ver.ver(), self.tag.encode());
+        result.push(ver.len() as u8);
+        result.extend_from_slice(ver.as_bytes());
+        result.push(tag.len() as u8);
+        result.extend_from_slice(tag.as_bytes());
+
+        result.push(self.checksum.len() as u8);
+        result.extend_from_slice(&self.checksum);
+
+        match self.min_size {
+            MinSizePolicy::Standard => result.push(0),
+            MinSizePolicy::Conservative => result.push(1),
+            MinSizePolicy::Force => result.push(2),
+            MinSizePolicy::Custom {
+                min_len,
+                min_buckets,
+            } => {
+                result.push(3);
+                write_varint(&mut result, min_len as u64);
+                write_varint(&mut result, min_buckets as u64);
+            }
+        }
+
+        write_varint(&mut result, self.data_len);
+        for &count in &self.buckets {
+            write_varint(&mut result, count);
+        }
+
+        result
+    }
+
+    /// Parses a histogram serialised with [`TxLshHistogram::to_bytes`].
+    ///
+    /// Digests of a custom version can only be parsed once their prefix is registered.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, TxLshError> {
+        let mut reader = ByteReader::new(data);
+
+        if reader.bytes(MAGIC.len())? != MAGIC || reader.byte()? != FORMAT_VERSION {
+            Err(TxLshError::InvalidBytes)?
+        }
+
+        let len = reader.byte()? as usize;
+        let ver = std::str::from_utf8(reader.bytes(len)?).map_err(|_| TxLshError::InvalidBytes)?;
+        let ver = Version::from_ver(ver).ok_or(TxLshError::InvalidVersion)?;
+
+        let len = reader.byte()? as usize;
+        let tag = std::str::from_utf8(reader.bytes(len)?).map_err(|_| TxLshError::InvalidBytes)?;
+        let tag = match Tag::decode(tag)? {
+            (tag, read) if read == len => tag,
+            _ => Err(TxLshError::InvalidBytes)?,
+        };
+
+        let len = reader.byte()? as usize;
+        let checksum_kind = match len {
+            1 => ChecksumKind::OneByte,
+            3 => ChecksumKind::ThreeByte,
+            _ => Err(TxLshError::InvalidBytes)?,
+        };
+        let checksum = reader.bytes(len)?.to_vec();
+
+        let min_size = match reader.byte()? {
+            0 => MinSizePolicy::Standard,
+            1 => MinSizePolicy::Conservative,
+            2 => MinSizePolicy::Force,
+            3 => MinSizePolicy::Custom {
+                min_len: reader.varint()? as usize,
+                min_buckets: reader.varint()? as usize,
+            },
+            _ => Err(TxLshError::InvalidBytes)?,
+        };
+
+        let data_len = reader.varint()?;
+        let mut buckets = [0; BUCKET_SIZE];
+        for count in buckets.iter_mut() {
+            *count = reader.varint()?;
+        }
+
+        if !reader.is_empty() {
+            Err(TxLshError::InvalidBytes)?
+        }
+
+        Ok(Self {
+            buckets,
+            checksum_kind,
+            checksum,
+            data_len,
+            min_size,
+            ver,
+            tag,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{QuantileKind, TxLshBuilder};
+
+    static LOREM: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+
+    fn build(data: &[u8], bucket: BucketKind) -> (TxLshHistogram, TxLsh) {
+        let mut builder = TxLshBuilder::new(bucket, ChecksumKind::ThreeByte, Version::Version4)
+            .with_quantiles(QuantileKind::Octile)
+            .with_min_size(MinSizePolicy::Custom {
+                min_len: 20,
+                min_buckets: 10,
+            });
+        builder.update(data);
+
+        (builder.histogram(), builder.build().unwrap())
+    }
+
+    #[test]
+    fn test_to_txlsh() {
+        let (histogram, digest) = build(LOREM, BucketKind::Bucket256);
+        assert_eq!(digest, histogram.to_txlsh(BucketKind::Bucket256).unwrap());
+
+        let (_, digest) = build(LOREM, BucketKind::Bucket128);
+        assert_eq!(digest, histogram.to_txlsh(BucketKind::Bucket128).unwrap());
+
+        let parsed = TxLshHistogram::from_bytes(&histogram.to_bytes()).unwrap();
+        assert_eq!(histogram, parsed);
+        assert!(TxLshHistogram::from_bytes(&histogram.to_bytes()[..100]).is_err());
+    }
+
+    #[test]
+    fn test_distances() {
+        let (x, _) = build(LOREM, BucketKind::Bucket256);
+        let (y, _) = build(&LOREM[..300], BucketKind::Bucket256);
+
+        assert_eq!(0., x.cosine_distance(&x));
+        assert_eq!(0., x.jensen_shannon_distance(&x));
+        assert_eq!(0, x.l1_distance(&x));
+
+        let (cosine, js) = (x.cosine_distance(&y), x.jensen_shannon_distance(&y));
+        assert!(cosine > 0. && cosine < 1.);
+        assert!(js > 0. && js < 1.);
+        assert_eq!(cosine, y.cosine_distance(&x));
+        assert_eq!(
+            x.buckets().iter().sum::<u64>() - y.buckets().iter().sum::<u64>(),
+            x.l1_distance(&y)
+        );
+    }
+}
@@ -1,19 +1,50 @@
 use pyo3::{prelude::*, types::PyBytes};
 mod consts;
 mod helper;
-pub use crate::helper::{BucketKind, ChecksumKind, Version};
+pub use crate::helper::{
+    BucketKind, CancelToken, ChecksumKind, CustomVersion, LengthKind, MinSizePolicy, QuantileKind,
+    RunFilter, Sampling, TripletSelection, Version, WindowKind,
+};
 
 mod error;
 pub use error::TxLshError;
 
+mod tag;
+
 mod hash_funcs;
 use crate::hash_funcs::pearson_h;
+pub use crate::hash_funcs::{Pearson, Resolved, TripletHasher, Xxh3};
 
 mod txlsh_mod;
 pub use crate::txlsh_mod::{TxLsh, TxLshBuilder};
 
+mod histogram;
+pub use crate::histogram::TxLshHistogram;
+
+mod chunked;
+pub use crate::chunked::{ChunkKind, TxLshChunk, TxLshChunkSet};
+
+mod centroid;
+pub use crate::centroid::TxLshCentroid;
+
+mod multi_builder;
+pub use crate::multi_builder::TxLshMultiBuilder;
+
+mod window_builder;
+pub use crate::window_builder::TxLshWindowBuilder;
+
+mod slab;
+pub use crate::slab::TxLshSlab;
+
+#[cfg(feature = "rayon")]
+mod tree;
+#[cfg(feature = "rayon")]
+pub use crate::tree::{hash_tree, SymlinkPolicy, TreeEntry, TreeOptions};
+
 mod txlsh_builders;
-pub use crate::txlsh_builders::{default_builder, full_builder,tx_lsh_builder};
+pub use crate::txlsh_builders::{default_builder, full_builder,tx_lsh_builder, hash_parallel};
+#[cfg(feature = "mmap")]
+pub use crate::txlsh_builders::hash_file_parallel;
 
 /// Pearson hash exposed for Python
 #[pyfunction]
@@ -28,7 +59,7 @@ fn default_hash(binary_data: &PyBytes) -> PyResult<String> {
         let mut builder = default_builder();
         builder.update(binary_data.as_bytes());
         match builder.build() {
-            Ok(result) => Ok(String::from(result.hash())),
+            Ok(result) => Ok(result.hash()),
             // python implementation doesn't really address error propagation.
             // not long enough, q3=0 all just becomes null
             Err(_) => Ok(String::from("TNULL"))
@@ -40,7 +71,7 @@ fn full_hash(binary_data: &PyBytes) -> PyResult<String> {
         let mut builder = full_builder();
         builder.update(binary_data.as_bytes());
         match builder.build() {
-            Ok(result) => Ok(String::from(result.hash())),
+            Ok(result) => Ok(result.hash()),
             // python implementation doesn't really address error propagation.
             // not long enough, q3=0 all just becomes null
             Err(_) => Ok(String::from("TNULL"))
@@ -52,7 +83,7 @@ fn txlsh_hash(binary_data: &PyBytes) -> PyResult<String> {
         let mut builder = tx_lsh_builder();
         builder.update(binary_data.as_bytes());
         match builder.build() {
-            Ok(result) => Ok(String::from(result.hash())),
+            Ok(result) => Ok(result.hash()),
             // python implementation doesn't really address error propagation.
             // not long enough, q3=0 all just becomes null
             Err(_) => Ok(String::from("TNULL"))
@@ -0,0 +1,168 @@
+use crate::{BucketKind, ChecksumKind, TxLsh, TxLshBuilder, TxLshError, Version};
+
+/// A builder producing several digest variants of the same input in a single pass.
+///
+/// Variants whose triplets are hashed by the same function share one [`TxLshBuilder`]: both
+/// Pearson-based versions (```T1``` and the original TLSH) are fed once, and the
+/// 128 and 256 bucket digests are read from the same buckets. A one-byte checksum is the first
+/// byte of the three-byte one, so both are computed together as well.
+#[derive(Clone, Debug)]
+pub struct TxLshMultiBuilder {
+    variants: Vec<(BucketKind, ChecksumKind, Version)>,
+    /// Index in ```builders``` of the builder shared by each variant, or ```None``` for custom
+    /// versions, which have no hash function to build with.
+    groups: Vec<Option<usize>>,
+    builders: Vec<TxLshBuilder>,
+}
+
+impl TxLshMultiBuilder {
+    /// Constructs a builder for the given variants, as passed to [`TxLshBuilder::new`].
+    pub fn new(variants: &[(BucketKind, ChecksumKind, Version)]) -> Self {
+        // Hash function and checksum of each shared builder.
+        let mut hashers: Vec<(Version, ChecksumKind)> = Vec::new();
+        let mut groups = Vec::with_capacity(variants.len());
+
+        for &(_, checksum, ver) in variants {
+            let hasher = match ver {
+                Version::TxLshV1 | Version::TxLshV2 => ver,
+                Version::Custom(_) => {
+                    groups.push(None);
+                    continue;
+                }
+                _ => Version::Version4,
+            };
+
+            match hashers.iter().position(|&(h, _)| h == hasher) {
+                Some(idx) => {
+                    if checksum == ChecksumKind::ThreeByte {
+                        hashers[idx].1 = checksum;
+                    }
+                    groups.push(Some(idx));
+                }
+                None => {
+                    hashers.push((hasher, checksum));
+                    groups.push(Some(hashers.len() - 1));
+                }
+            }
+        }
+
+        let builders = hashers
+            .into_iter()
+            .map(|(hasher, checksum)| TxLshBuilder::new(BucketKind::Bucket256, checksum, hasher))
+            .collect();
+
+        Self {
+            variants: variants.to_vec(),
+            groups,
+            builders,
+        }
+    }
+
+    /// Returns the number of passes over each input byte, one per distinct hash function.
+    pub fn pass_count(&self) -> usize {
+        self.builders.len()
+    }
+
+    /// Processes an input stream.
+    pub fn update(&mut self, data: &[u8]) {
+        self.update_from(data, 0, data.len());
+    }
+
+    /// Reads an input stream from an offset an processes it.
+    ///
+    /// See [`TxLshBuilder::update_from`].
+    pub fn update_from(&mut self, data: &[u8], offset: usize, len: usize) {
+        for builder in self.builders.iter_mut() {
+            builder.update_from(data, offset, len);
+        }
+    }
+
+    /// Builds every variant, in the order they were given to [`TxLshMultiBuilder::new`].
+    ///
+    /// A variant may fail while others succeed, e.g. an input filling enough buckets for 128
+    /// buckets but not for 256. Variants of a custom version always fail with
+    /// [`TxLshError::UnboundVersion`].
+    pub fn build(&self) -> Vec<Result<TxLsh, TxLshError>> {
+        let histograms: Vec<_> = self.builders.iter().map(|b| b.histogram()).collect();
+
+        self.variants
+            .iter()
+            .zip(&self.groups)
+            .map(|(&(bucket, checksum, ver), &idx)| {
+                let idx = idx.ok_or(TxLshError::UnboundVersion)?;
+                let mut histogram = histograms[idx].clone();
+                histogram.ver = ver;
+                histogram.checksum_kind = checksum;
+                histogram.checksum.truncate(checksum.checksum_len());
+
+                histogram.to_txlsh(bucket)
+            })
+            .collect()
+    }
+
+    /// Clears the state of the builder, removing all data.
+    pub fn reset(&mut self) {
+        for builder in self.builders.iter_mut() {
+            builder.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static LOREM: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+
+    #[test]
+    fn test_multi_builder() {
+        let variants = [
+            (
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Version4,
+            ),
+            (
+                BucketKind::Bucket256,
+                ChecksumKind::ThreeByte,
+                Version::Version4,
+            ),
+            (
+                BucketKind::Bucket256,
+                ChecksumKind::ThreeByte,
+                Version::TxLshV1,
+            ),
+            (
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Original,
+            ),
+        ];
+        let mut multi = TxLshMultiBuilder::new(&variants);
+        assert_eq!(2, multi.pass_count());
+
+        multi.update_from(LOREM, 0, 200);
+        multi.update_from(LOREM, 200, LOREM.len() - 200);
+
+        for (&(bucket, checksum, ver), result) in variants.iter().zip(multi.build()) {
+            let mut builder = TxLshBuilder::new(bucket, checksum, ver);
+            builder.update(LOREM);
+            assert_eq!(builder.build().unwrap(), result.unwrap());
+        }
+
+        let custom = Version::custom("Y5").unwrap();
+        let mut multi = TxLshMultiBuilder::new(&[
+            (BucketKind::Bucket128, ChecksumKind::OneByte, custom),
+            (
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Original,
+            ),
+        ]);
+        multi.update(LOREM);
+        assert_eq!(1, multi.pass_count());
+        let results = multi.build();
+        assert!(matches!(results[0], Err(TxLshError::UnboundVersion)));
+        assert!(results[1].is_ok());
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{
+    helper::{bit_distance, code_distance},
+    QuantileKind, TxLsh, TxLshError,
+};
+
+/// Digests built with the same settings, with their codes stored contiguously for brute-force
+/// scans, e.g. over millions of digests.
+///
+/// [`TxLshSlab::distances`] compares a query with every digest at once. Quartile codes, the
+/// default, are compared with SSE2 or AVX2 when the CPU supports them, detected at runtime.
+#[derive(Clone, Debug, Default)]
+pub struct TxLshSlab {
+    /// First digest pushed, holding the settings of the slab.
+    template: Option<TxLsh>,
+    /// Length and quartile ratios of each digest.
+    headers: Vec<(usize, usize, usize)>,
+    checksums: Vec<u8>,
+    codes: Vec<u8>,
+}
+
+impl TxLshSlab {
+    /// Constructs an empty slab.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of digests in the slab.
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    /// Returns whether the slab holds no digest.
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Appends a digest.
+    ///
+    /// Fails with [`TxLshError::IncompatibleDigests`] if it was built with other settings than
+    /// the digests already in the slab.
+    pub fn push(&mut self, digest: &TxLsh) -> Result<(), TxLshError> {
+        let template = self.template.get_or_insert_with(|| digest.clone());
+        if !template.is_comparable(digest) {
+            Err(TxLshError::IncompatibleDigests)?
+        }
+
+        self.headers
+            .push((digest.len, digest.q1ratio, digest.q2ratio));
+        self.checksums.extend_from_slice(&digest.checksum);
+        self.codes.extend_from_slice(&digest.codes);
+
+        Ok(())
+    }
+
+    /// Calculates the difference between ```query``` and every digest of the slab, in the order
+    /// they were pushed, as [`TxLsh::diff`] does.
+    ///
+    /// Fails with [`TxLshError::IncompatibleDigests`] if the query was built with other settings
+    /// than the digests of the slab.
+    pub fn distances(&self, query: &TxLsh, with_len: bool) -> Result<Vec<usize>, TxLshError> {
+        if self
+            .template
+            .as_ref()
+            .is_some_and(|t| !t.is_comparable(query))
+        {
+            Err(TxLshError::IncompatibleDigests)?
+        }
+
+        let mut result = vec![0; self.len()];
+        if query.tag.quantile_kind == QuantileKind::Quartile {
+            quartile_distances(Kernel::detect(), &query.codes, &self.codes, &mut result);
+        } else {
+            for (distance, codes) in result.iter_mut().zip(self.codes.chunks(query.codes.len())) {
+                *distance = code_distance(&query.codes, codes, query.tag.quantile_kind);
+            }
+        }
+
+        let checksums = self.checksums.chunks_exact(query.checksum.len());
+        for ((distance, &header), checksum) in result.iter_mut().zip(&self.headers).zip(checksums) {
+            *distance += query.header_diff(header, checksum, with_len);
+        }
+
+        Ok(result)
+    }
+}
+
+/// An implementation of the quartile code distance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kernel {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+}
+
+impl Kernel {
+    /// Returns the fastest kernel supported by the CPU.
+    fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Kernel::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return Kernel::Sse2;
+            }
+        }
+
+        Kernel::Scalar
+    }
+}
+
+/// Writes the distance between the quartile codes of ```query``` and each of those of ```slab```,
+/// stored one after the other, into ```result```.
+fn quartile_distances(kernel: Kernel, query: &[u8], slab: &[u8], result: &mut [usize]) {
+    let slab = slab.chunks_exact(query.len()).zip(result.iter_mut());
+
+    match kernel {
+        Kernel::Scalar => {
+            for (codes, distance) in slab {
+                *distance = bit_distance(query, codes);
+            }
+        }
+        // Safety: the kernels are only selected when the CPU supports their instructions.
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Sse2 => unsafe { x86::quartile_distances_sse2(query, slab) },
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 => unsafe { x86::quartile_distances_avx2(query, slab) },
+    }
+}
+
+/// Quartile code distances over 16 or 32 bytes at once.
+///
+/// Each byte holds four 2-bit codes. Every code is isolated by a shift and a mask, and the
+/// absolute differences are summed byte-wise, counting opposite codes twice, before being added
+/// horizontally.
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use crate::helper::bit_distance;
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn quartile_distances_sse2<'a>(
+        query: &[u8],
+        slab: impl Iterator<Item = (&'a [u8], &'a mut usize)>,
+    ) {
+        for (codes, distance) in slab {
+            *distance = quartile_distance_sse2(query, codes);
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn quartile_distances_avx2<'a>(
+        query: &[u8],
+        slab: impl Iterator<Item = (&'a [u8], &'a mut usize)>,
+    ) {
+        for (codes, distance) in slab {
+            *distance = quartile_distance_avx2(query, codes);
+        }
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    unsafe fn quartile_distance_sse2(x: &[u8], y: &[u8]) -> usize {
+        let (mask, zero) = (_mm_set1_epi8(3), _mm_setzero_si128());
+        let mut total = zero;
+        let split = x.len() - x.len() % 16;
+
+        for offset in (0..split).step_by(16) {
+            let a = _mm_loadu_si128(x.as_ptr().add(offset) as *const __m128i);
+            let b = _mm_loadu_si128(y.as_ptr().add(offset) as *const __m128i);
+
+            let code_diff = |a: __m128i, b: __m128i| {
+                let (a, b) = (_mm_and_si128(a, mask), _mm_and_si128(b, mask));
+                let diff = _mm_sub_epi8(_mm_max_epu8(a, b), _mm_min_epu8(a, b));
+                _mm_add_epi8(diff, _mm_and_si128(_mm_cmpeq_epi8(diff, mask), mask))
+            };
+            let sum = _mm_add_epi8(
+                _mm_add_epi8(
+                    code_diff(a, b),
+                    code_diff(_mm_srli_epi16::<2>(a), _mm_srli_epi16::<2>(b)),
+                ),
+                _mm_add_epi8(
+                    code_diff(_mm_srli_epi16::<4>(a), _mm_srli_epi16::<4>(b)),
+                    code_diff(_mm_srli_epi16::<6>(a), _mm_srli_epi16::<6>(b)),
+                ),
+            );
+            total = _mm_add_epi64(total, _mm_sad_epu8(sum, zero));
+        }
+
+        let high = _mm_unpackhi_epi64(total, total);
+        let total = _mm_cvtsi128_si64(total) + _mm_cvtsi128_si64(high);
+
+        total as usize + bit_distance(&x[split..], &y[split..])
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn quartile_distance_avx2(x: &[u8], y: &[u8]) -> usize {
+        let (mask, zero) = (_mm256_set1_epi8(3), _mm256_setzero_si256());
+        let mut total = zero;
+        let split = x.len() - x.len() % 32;
+
+        for offset in (0..split).step_by(32) {
+            let a = _mm256_loadu_si256(x.as_ptr().add(offset) as *const __m256i);
+            let b = _mm256_loadu_si256(y.as_ptr().add(offset) as *const __m256i);
+
+            let code_diff = |a: __m256i, b: __m256i| {
+                let (a, b) = (_mm256_and_si256(a, mask), _mm256_and_si256(b, mask));
+                let diff = _mm256_sub_epi8(_mm256_max_epu8(a, b), _mm256_min_epu8(a, b));
+                _mm256_add_epi8(diff, _mm256_and_si256(_mm256_cmpeq_epi8(diff, mask), mask))
+            };
+            let sum = _mm256_add_epi8(
+                _mm256_add_epi8(
+                    code_diff(a, b),
+                    code_diff(_mm256_srli_epi16::<2>(a), _mm256_srli_epi16::<2>(b)),
+                ),
+                _mm256_add_epi8(
+                    code_diff(_mm256_srli_epi16::<4>(a), _mm256_srli_epi16::<4>(b)),
+                    code_diff(_mm256_srli_epi16::<6>(a), _mm256_srli_epi16::<6>(b)),
+                ),
+            );
+            total = _mm256_add_epi64(total, _mm256_sad_epu8(sum, zero));
+        }
+
+        let total = _mm_add_epi64(
+            _mm256_castsi256_si128(total),
+            _mm256_extracti128_si256::<1>(total),
+        );
+        let high = _mm_unpackhi_epi64(total, total);
+        let total = _mm_cvtsi128_si64(total) + _mm_cvtsi128_si64(high);
+
+        total as usize + bit_distance(&x[split..], &y[split..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BucketKind, ChecksumKind, TxLshBuilder, Version};
+
+    static LOREM: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+
+    #[test]
+    fn test_slab() {
+        for (bucket, quantile) in [
+            (BucketKind::Bucket128, QuantileKind::Quartile),
+            (BucketKind::Bucket256, QuantileKind::Quartile),
+            (BucketKind::Bucket256, QuantileKind::Octile),
+        ] {
+            let digests: Vec<TxLsh> = (0..20)
+                .map(|ii| {
+                    let mut builder =
+                        TxLshBuilder::new(bucket, ChecksumKind::ThreeByte, Version::Version4)
+                            .with_quantiles(quantile);
+                    builder.update(&LOREM[(ii * 7)..]);
+                    builder.build().unwrap()
+                })
+                .collect();
+
+            let mut slab = TxLshSlab::new();
+            for digest in &digests {
+                slab.push(digest).unwrap();
+            }
+
+            for with_len in [false, true] {
+                let expected: Vec<usize> = digests
+                    .iter()
+                    .map(|d| digests[3].diff(d, with_len))
+                    .collect();
+                assert_eq!(expected, slab.distances(&digests[3], with_len).unwrap());
+            }
+        }
+
+        // The kernels available on this CPU give the same distances as the scalar one.
+        let mut state = 1u32;
+        let mut bytes = || {
+            (0..(64 * 50 + 64))
+                .map(|_| {
+                    state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                    (state >> 24) as u8
+                })
+                .collect::<Vec<u8>>()
+        };
+        let (codes, query) = (bytes(), bytes());
+        let mut expected = vec![0; 51];
+        quartile_distances(Kernel::Scalar, &query[..64], &codes, &mut expected);
+
+        let mut result = vec![0; 51];
+        quartile_distances(Kernel::detect(), &query[..64], &codes, &mut result);
+        assert_eq!(expected, result);
+        #[cfg(target_arch = "x86_64")]
+        {
+            quartile_distances(Kernel::Sse2, &query[..64], &codes, &mut result);
+            assert_eq!(expected, result);
+        }
+
+        let build = |checksum| {
+            let mut builder = TxLshBuilder::new(BucketKind::Bucket128, checksum, Version::Version4);
+            builder.update(LOREM);
+            builder.build().unwrap()
+        };
+        let mut slab = TxLshSlab::new();
+        slab.push(&build(ChecksumKind::ThreeByte)).unwrap();
+        assert!(slab.push(&build(ChecksumKind::OneByte)).is_err());
+        assert!(slab.distances(&build(ChecksumKind::OneByte), true).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::error::TxLshError;
+use crate::helper::{LengthKind, QuantileKind, RunFilter, Sampling, WindowKind};
+
+/// Marker letters in the order in which they are encoded.
+const MARKERS: &str = "WSQLKRP";
+
+/// Settings written between the version prefix and the checksum of a hash string.
+///
+/// Each non-default setting is encoded as a marker letter outside the hex alphabet followed by a
+/// fixed number of hex digits, so that digests built with the reference settings keep the exact
+/// format of the original TLSH.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub(crate) struct Tag {
+    pub(crate) window_kind: WindowKind,
+    /// Fingerprint of a triplet selection other than the default one of the window.
+    pub(crate) triplets: Option<u16>,
+    pub(crate) quantile_kind: QuantileKind,
+    pub(crate) length_kind: LengthKind,
+    /// Fingerprint of the secret key of a keyed hasher.
+    pub(crate) key: Option<u16>,
+    pub(crate) run_filter: RunFilter,
+    pub(crate) sampling: Option<Sampling>,
+}
+
+impl Tag {
+    /// Returns the number of characters taken by the tag in a hash string.
+    pub(crate) fn len(&self) -> usize {
+        self.encode().len()
+    }
+
+    pub(crate) fn encode(&self) -> String {
+        let mut result = String::new();
+
+        if self.window_kind != WindowKind::default() {
+            result.push_str(&format!("W{:X}", self.window_kind.window_size()));
+        }
+
+        if let Some(fingerprint) = self.triplets {
+            result.push_str(&format!("S{:04X}", fingerprint));
+        }
+
+        if self.quantile_kind != QuantileKind::default() {
+            result.push_str(&format!("Q{:X}", self.quantile_kind.code_bits()));
+        }
+
+        if self.length_kind != LengthKind::default() {
+            result.push_str(&format!("L{:X}", self.length_kind.id()));
+        }
+
+        if let Some(fingerprint) = self.key {
+            result.push_str(&format!("K{:04X}", fingerprint));
+        }
+
+        if self.run_filter != RunFilter::default() {
+            result.push_str(&format!(
+                "R{:X}{:04X}",
+                self.run_filter.id(),
+                self.run_filter.max_run()
+            ));
+        }
+
+        if let Some(sampling) = self.sampling {
+            let (block_bits, stride, seed) = sampling.parts();
+            result.push_str(&format!("P{:02X}{:04X}{:04X}", block_bits, stride, seed));
+        }
+
+        result
+    }
+
+    /// Parses the tag at the start of ```s``` and returns it with the number of characters read.
+    ///
+    /// Markers must appear in the order in which they are encoded, each at most once.
+    pub(crate) fn decode(s: &str) -> Result<(Self, usize), TxLshError> {
+        let mut tag = Tag::default();
+        let mut offset = 0;
+        // Rank in MARKERS of the first marker still allowed.
+        let mut next = 0;
+
+        while let Some(marker) = s[offset..].chars().next() {
+            if let Some(rank) = MARKERS.find(marker) {
+                if rank < next {
+                    Err(TxLshError::InvalidHashValue)?
+                }
+                next = rank + 1;
+            }
+
+            match marker {
+                'W' => {
+                    let size = usize::from_str_radix(field(s, offset + 1, 1)?, 16)?;
+                    tag.window_kind =
+                        WindowKind::from_window_size(size).ok_or(TxLshError::InvalidHashValue)?;
+                    offset += 2;
+                }
+                'S' => {
+                    tag.triplets = Some(u16::from_str_radix(field(s, offset + 1, 4)?, 16)?);
+                    offset += 5;
+                }
+                'Q' => {
+                    let bits = usize::from_str_radix(field(s, offset + 1, 1)?, 16)?;
+                    tag.quantile_kind =
+                        QuantileKind::from_code_bits(bits).ok_or(TxLshError::InvalidHashValue)?;
+                    offset += 2;
+                }
+                'L' => {
+                    let id = usize::from_str_radix(field(s, offset + 1, 1)?, 16)?;
+                    tag.length_kind =
+                        LengthKind::from_id(id).ok_or(TxLshError::InvalidHashValue)?;
+                    offset += 2;
+                }
+                'K' => {
+                    tag.key = Some(u16::from_str_radix(field(s, offset + 1, 4)?, 16)?);
+                    offset += 5;
+                }
+                'R' => {
+                    let id = usize::from_str_radix(field(s, offset + 1, 1)?, 16)?;
+                    let n = u16::from_str_radix(field(s, offset + 2, 4)?, 16)?;
+                    tag.run_filter =
+                        RunFilter::from_id(id, n).ok_or(TxLshError::InvalidHashValue)?;
+                    offset += 6;
+                }
+                'P' => {
+                    let block_bits = u8::from_str_radix(field(s, offset + 1, 2)?, 16)?;
+                    let stride = u16::from_str_radix(field(s, offset + 3, 4)?, 16)?;
+                    let seed = u16::from_str_radix(field(s, offset + 7, 4)?, 16)?;
+                    tag.sampling = Some(
+                        Sampling::from_parts(block_bits, stride, seed)
+                            .ok_or(TxLshError::InvalidHashValue)?,
+                    );
+                    offset += 11;
+                }
+                '0'..='9' | 'A'..='F' => break,
+                _ => Err(TxLshError::InvalidHashValue)?,
+            }
+        }
+
+        Ok((tag, offset))
+    }
+}
+
+/// Returns the ```len``` hex digits at ```offset```, which unlike ```from_str_radix``` rejects a
+/// leading sign.
+fn field(s: &str, offset: usize, len: usize) -> Result<&str, TxLshError> {
+    let field = s
+        .get(offset..(offset + len))
+        .ok_or(TxLshError::InvalidHashValue)?;
+    if !field.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Err(TxLshError::ParseHexFailed)?
+    }
+
+    Ok(field)
+}
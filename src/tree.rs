@@ -0,0 +1,292 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{TripletHasher, TxLsh, TxLshBuilder, TxLshError, Version};
+
+/// An enum determining how symbolic links met by [`hash_tree`] are processed.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub enum SymlinkPolicy {
+    /// Links are left out of the scan.
+    #[default]
+    Skip,
+    /// Links are followed, into directories as well. Loops are reported as errors.
+    Follow,
+}
+
+/// The settings of a [`hash_tree`] scan, generic over the hasher of the builder as
+/// [`TxLshBuilder`] is.
+#[derive(Clone, Debug)]
+pub struct TreeOptions<H: TripletHasher = Version> {
+    builder: TxLshBuilder<H>,
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    symlinks: SymlinkPolicy,
+    min_size: u64,
+    max_size: u64,
+    max_depth: usize,
+}
+
+impl<H: TripletHasher> TreeOptions<H> {
+    /// Constructs options hashing every file with the settings of ```builder```.
+    pub fn new(builder: TxLshBuilder<H>) -> Self {
+        Self {
+            builder,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            symlinks: SymlinkPolicy::default(),
+            min_size: 0,
+            max_size: u64::MAX,
+            max_depth: usize::MAX,
+        }
+    }
+
+    /// Restricts the scan to files matching ```pattern```, e.g. ```"**/*.txt"```, relative to the
+    /// root of the scan. Files matching any of the included patterns are hashed.
+    ///
+    /// Fails with [`TxLshError::InvalidGlob`] if the pattern is malformed.
+    pub fn with_include(mut self, pattern: &str) -> Result<Self, TxLshError> {
+        self.include.push(Glob::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Leaves out files and directories matching ```pattern```, e.g. ```"target"```, relative
+    /// to the root of the scan.
+    ///
+    /// Fails with [`TxLshError::InvalidGlob`] if the pattern is malformed.
+    pub fn with_exclude(mut self, pattern: &str) -> Result<Self, TxLshError> {
+        self.exclude.push(Glob::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Sets how symbolic links are processed.
+    pub fn with_symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Leaves out files smaller than ```min``` or larger than ```max``` bytes.
+    pub fn with_size_limits(mut self, min: u64, max: u64) -> Self {
+        self.min_size = min;
+        self.max_size = max;
+        self
+    }
+
+    /// Limits the scan to ```depth``` levels of directories below the root.
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+}
+
+/// The result of hashing one file of a [`hash_tree`] scan.
+#[derive(Debug)]
+pub struct TreeEntry {
+    /// Path of the file, starting with the root of the scan.
+    pub path: PathBuf,
+    /// Size of the file in bytes, or 0 if it cannot be read.
+    pub size: u64,
+    /// Digest of the file, or the error met while reading or hashing it.
+    pub digest: Result<TxLsh, TxLshError>,
+}
+
+/// Hashes every file below ```root``` on the rayon thread pool.
+///
+/// Entries are yielded as files are hashed, in no particular order. A file or directory that
+/// cannot be read yields an entry holding the error, and the rest of the scan goes on.
+///
+/// Fails with [`TxLshError::InvalidGlob`] if the patterns of ```options``` cannot be compiled.
+pub fn hash_tree<P: AsRef<Path>, H: TripletHasher + Send + Sync + 'static>(
+    root: P,
+    options: &TreeOptions<H>,
+) -> Result<impl Iterator<Item = TreeEntry>, TxLshError> {
+    let root = root.as_ref().to_path_buf();
+    let include = (!options.include.is_empty())
+        .then(|| glob_set(&options.include))
+        .transpose()?;
+    let exclude = glob_set(&options.exclude)?;
+    let options = options.clone();
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let walker = WalkDir::new(&root)
+            .follow_links(options.symlinks == SymlinkPolicy::Follow)
+            .max_depth(options.max_depth.saturating_add(1))
+            .into_iter()
+            .filter_entry(|entry| !matches(&exclude, &root, entry));
+
+        walker
+            .filter_map(|entry| match entry {
+                Ok(entry) if entry.file_type().is_file() => {
+                    // Option::is_none_or would need Rust 1.82.
+                    #[allow(clippy::unnecessary_map_or)]
+                    let included = include
+                        .as_ref()
+                        .map_or(true, |include| matches(include, &root, &entry));
+                    included.then_some(Ok(entry))
+                }
+                Ok(_) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .par_bridge()
+            // The scan stops once the receiver is dropped and sending fails.
+            .try_for_each_with(sender, |sender, entry| match hash_entry(entry, &options) {
+                Some(entry) => sender.send(entry).map_err(|_| ()),
+                None => Ok(()),
+            })
+            .ok();
+    });
+
+    Ok(receiver.into_iter())
+}
+
+fn glob_set(globs: &[Glob]) -> Result<GlobSet, TxLshError> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(glob.clone());
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Returns whether the path of ```entry``` relative to ```root``` matches any of ```globs```.
+fn matches(globs: &GlobSet, root: &Path, entry: &DirEntry) -> bool {
+    entry
+        .path()
+        .strip_prefix(root)
+        .is_ok_and(|path| globs.is_match(path))
+}
+
+fn hash_entry<H: TripletHasher>(
+    entry: Result<DirEntry, walkdir::Error>,
+    options: &TreeOptions<H>,
+) -> Option<TreeEntry> {
+    let entry = match entry {
+        Ok(entry) => entry,
+        Err(err) => {
+            return Some(TreeEntry {
+                path: err.path().map(Path::to_path_buf).unwrap_or_default(),
+                size: 0,
+                digest: Err(std::io::Error::from(err).into()),
+            })
+        }
+    };
+
+    let size = match entry.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(err) => {
+            return Some(TreeEntry {
+                path: entry.into_path(),
+                size: 0,
+                digest: Err(std::io::Error::from(err).into()),
+            })
+        }
+    };
+    if size < options.min_size || size > options.max_size {
+        return None;
+    }
+
+    let mut builder = options.builder.clone();
+    builder.reset();
+    let digest = builder
+        .update_file(entry.path(), |_, _| (), None)
+        .and_then(|_| builder.build());
+
+    Some(TreeEntry {
+        path: entry.into_path(),
+        size,
+        digest,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BucketKind, ChecksumKind, Xxh3};
+
+    static LOREM: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+
+    #[test]
+    fn test_hash_tree() {
+        // The directory is removed when dropped, even if an assertion fails.
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("sub/skipped")).unwrap();
+        std::fs::write(root.join("a.txt"), LOREM).unwrap();
+        std::fs::write(root.join("short.txt"), b"short").unwrap();
+        std::fs::write(root.join("sub/b.txt"), &LOREM[10..]).unwrap();
+        std::fs::write(root.join("sub/c.bin"), LOREM).unwrap();
+        std::fs::write(root.join("sub/skipped/d.txt"), LOREM).unwrap();
+
+        let builder = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        let options = TreeOptions::new(builder.clone())
+            .with_include("**/*.txt")
+            .unwrap()
+            .with_exclude("sub/skipped")
+            .unwrap();
+        let mut entries: Vec<_> = hash_tree(root, &options).unwrap().collect();
+        entries.sort_by(|x, y| x.path.cmp(&y.path));
+
+        let paths: Vec<_> = entries
+            .iter()
+            .map(|e| e.path.strip_prefix(root).unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                Path::new("a.txt"),
+                Path::new("short.txt"),
+                Path::new("sub/b.txt")
+            ],
+            paths
+        );
+        // A file too short to be hashed does not stop the scan.
+        assert!(entries[1].digest.is_err());
+
+        let mut expected = builder.clone();
+        expected.update(&LOREM[10..]);
+        assert_eq!(LOREM.len() as u64 - 10, entries[2].size);
+        assert_eq!(
+            &expected.build().unwrap(),
+            entries[2].digest.as_ref().unwrap()
+        );
+
+        let options = TreeOptions::new(builder.clone()).with_size_limits(100, u64::MAX);
+        assert_eq!(4, hash_tree(root, &options).unwrap().count());
+        let missing: Vec<_> = hash_tree(root.join("missing"), &options).unwrap().collect();
+        assert!(matches!(&missing[..], [entry] if entry.digest.is_err()));
+        assert!(TreeOptions::new(builder.clone())
+            .with_include("a[")
+            .is_err());
+
+        // Any hasher can be used.
+        let keyed = TxLshBuilder::with_hasher(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Xxh3::keyed(b"key"),
+        );
+        let mut expected = keyed.clone();
+        expected.update(LOREM);
+        let options = TreeOptions::new(keyed).with_include("a.txt").unwrap();
+        let entries: Vec<_> = hash_tree(root, &options).unwrap().collect();
+        assert_eq!(
+            &expected.build().unwrap(),
+            entries[0].digest.as_ref().unwrap()
+        );
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("sub"), root.join("link")).unwrap();
+            let options = TreeOptions::new(builder).with_size_limits(0, u64::MAX);
+            assert_eq!(5, hash_tree(root, &options).unwrap().count());
+            let options = options.with_symlinks(SymlinkPolicy::Follow);
+            assert_eq!(8, hash_tree(root, &options).unwrap().count());
+        }
+    }
+}
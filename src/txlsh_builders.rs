@@ -1,5 +1,5 @@
-use crate::TxLshBuilder;
 use crate::{BucketKind, ChecksumKind, Version};
+use crate::{TripletHasher, TxLsh, TxLshBuilder, TxLshError};
 
 pub fn default_builder() -> TxLshBuilder {
     TxLshBuilder::new(
@@ -24,3 +24,34 @@ pub fn tx_lsh_builder() -> TxLshBuilder {
         Version::TxLshV1,
     )
 }
+
+/// Hashes ```data``` on up to ```threads``` threads with the settings of ```builder```, e.g.
+/// [`full_builder`], ignoring any data it processed. The digest is the same as that of a
+/// sequential [`TxLshBuilder::update`].
+///
+/// See [`TxLshBuilder::update_parallel`] for how the input is split and the speedup it allows.
+pub fn hash_parallel<H: TripletHasher + Send + Sync>(
+    data: &[u8],
+    threads: usize,
+    builder: &TxLshBuilder<H>,
+) -> Result<TxLsh, TxLshError> {
+    let mut builder = builder.clone();
+    builder.reset();
+    builder.update_parallel(data, threads);
+    builder.build()
+}
+
+/// Hashes a whole file as [`hash_parallel`], reading it through a memory map.
+///
+/// The file must not be modified while it is processed. Requires the ```mmap``` feature.
+#[cfg(feature = "mmap")]
+pub fn hash_file_parallel<H: TripletHasher + Send + Sync, P: AsRef<std::path::Path>>(
+    path: P,
+    threads: usize,
+    builder: &TxLshBuilder<H>,
+) -> Result<TxLsh, TxLshError> {
+    let mut builder = builder.clone();
+    builder.reset();
+    builder.update_file_parallel(path, threads)?;
+    builder.build()
+}
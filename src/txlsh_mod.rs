@@ -1,38 +1,58 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::ops::AddAssign;
+use std::path::Path;
 use std::str::FromStr;
 
 use crate::{
-    hash_funcs::hasher,
-    helper::{bit_distance, find_quartiles, hash_len, l_capturing, mod_diff},
-    helper::{BucketKind, ChecksumKind, Version},
-    helper::{BUCKET_SIZE, WINDOW_SIZE},
-    TxLshError,
+    hash_funcs::{
+        is_unbound, Mixer, Pearson, PearsonTables, Plain, Resolved, TripletHasher, WideXxh3, Xxh3,
+    },
+    helper::{code_distance, code_len, hash_len, mod_diff},
+    helper::{write_varint, ByteReader, BUCKET_SIZE, MAX_WINDOW_SIZE},
+    helper::{BucketKind, ChecksumKind, LengthKind, MinSizePolicy, QuantileKind, RunFilter},
+    helper::{CancelToken, Sampling},
+    helper::{TripletSelection, Version, WindowKind},
+    tag::Tag,
+    TxLshError, TxLshHistogram,
 };
 
 const BUCKETS_A: [BucketKind; 2] = [BucketKind::Bucket128, BucketKind::Bucket256];
 const CHECKSUM_A: [ChecksumKind; 2] = [ChecksumKind::OneByte, ChecksumKind::ThreeByte];
-const VERSION_A: [Version; 3] = [Version::Original, Version::Version4, Version::TxLshV1];
+
+/// Size of the chunks read by [`TxLshBuilder::update_reader`], between which progress is reported.
+const READ_CHUNK: usize = 1 << 16;
+
+/// Smallest range of the input processed on its own thread by [`TxLshBuilder::update_parallel`].
+const MIN_PARALLEL_RANGE: usize = 1 << 16;
+
+/// Leading bytes of a builder snapshot, followed by the format version.
+const SNAPSHOT_MAGIC: &[u8] = b"TXB";
+const SNAPSHOT_VERSION: u8 = 1;
 
 /// A struct containing all required information from an input stream to generate a hash value.
 ///
 /// An instance of this struct can be obtained by calling the function [`TxLshBuilder::build`].
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TxLsh {
-    bucket_kind: BucketKind,
-    checksum_kind: ChecksumKind,
-    ver: Version,
-    checksum: Vec<u8>,
-    len: usize,
-    q1ratio: usize,
-    q2ratio: usize,
-    codes: Vec<u8>,
+    pub(crate) bucket_kind: BucketKind,
+    pub(crate) checksum_kind: ChecksumKind,
+    pub(crate) ver: Version,
+    pub(crate) tag: Tag,
+    pub(crate) checksum: Vec<u8>,
+    pub(crate) len: usize,
+    pub(crate) q1ratio: usize,
+    pub(crate) q2ratio: usize,
+    pub(crate) codes: Vec<u8>,
 }
 
 impl TxLsh {
     /// Computes and returns the hash value in hex-encoded string format.
     pub fn hash(&self) -> String {
-        let cap = hash_len(self.bucket_kind, self.checksum_kind, self.ver);
+        let cap = hash_len(self.bucket_kind, self.checksum_kind, self.ver, &self.tag);
         let mut result = String::with_capacity(cap);
         result.push_str(self.ver.ver());
+        result.push_str(&self.tag.encode());
 
         for ii in 0..self.checksum.len() {
             result.push_str(
@@ -42,8 +62,9 @@ impl TxLsh {
                     .collect::<String>(),
             );
         }
+        let len_width = self.tag.length_kind.len_bytes() << 1;
         result.push_str(
-            &format!("{:02X}", self.len as u32)
+            &format!("{:0width$X}", self.len, width = len_width)
                 .chars()
                 .rev()
                 .collect::<String>(),
@@ -58,57 +79,119 @@ impl TxLsh {
         result
     }
 
+    /// Calculates the difference between two TxLsh values, failing if they were built with different
+    /// versions, settings or keys.
+    ///
+    /// ```with_len``` controls whether the difference in length should be also considered in the calculation.
+    pub fn try_diff(&self, other: &TxLsh, with_len: bool) -> Result<usize, TxLshError> {
+        if !self.is_comparable(other) {
+            Err(TxLshError::IncompatibleDigests)?
+        }
+
+        Ok(self.diff(other, with_len))
+    }
+
+    /// Returns whether two digests were built with the same settings.
+    pub(crate) fn is_comparable(&self, other: &TxLsh) -> bool {
+        self.bucket_kind == other.bucket_kind
+            && self.checksum_kind == other.checksum_kind
+            && self.ver == other.ver
+            && self.tag == other.tag
+    }
+
     /// Calculates the difference between two TxLsh values.
     ///
+    /// The values must be comparable, see [`TxLsh::try_diff`]. This is checked in debug builds
+    /// only, and the result is meaningless otherwise.
+    ///
     /// ```with_len``` controls whether the difference in length should be also considered in the calculation.
     pub fn diff(&self, other: &TxLsh, with_len: bool) -> usize {
+        debug_assert!(
+            self.is_comparable(other),
+            "digests built with different settings"
+        );
+        let header = (other.len, other.q1ratio, other.q2ratio);
+
+        self.header_diff(header, &other.checksum, with_len)
+            + code_distance(&self.codes, &other.codes, self.tag.quantile_kind)
+    }
+
+    /// Calculates the part of the difference not coming from the codes, with the length, ratios
+    /// and checksum of another digest.
+    pub(crate) fn header_diff(
+        &self,
+        (len, q1ratio, q2ratio): (usize, usize, usize),
+        checksum: &[u8],
+        with_len: bool,
+    ) -> usize {
         let mut result = 0;
 
         if with_len {
-            match mod_diff(self.len, other.len, 256) {
-                x @ 0..=1 => result = x,
-                x => result = x * 12,
-            };
+            result = self.tag.length_kind.len_distance(self.len, len);
         }
 
-        match mod_diff(self.q1ratio, other.q1ratio, 16) {
+        match mod_diff(self.q1ratio, q1ratio, 16) {
             x @ 0..=1 => result += x,
             x => result += (x - 1) * 12,
         }
 
-        match mod_diff(self.q2ratio, other.q2ratio, 16) {
+        match mod_diff(self.q2ratio, q2ratio, 16) {
             x @ 0..=1 => result += x,
             x => result += (x - 1) * 12,
         }
 
-        for ii in 0..self.checksum.len() {
-            if self.checksum[ii] != other.checksum[ii] {
-                result += 1;
-                break;
-            }
+        if self.checksum != checksum {
+            result += 1;
         }
 
-        result += bit_distance(&self.codes, &other.codes);
-
         result
     }
+
+    /// Returns the index of the digest minimising the total difference to the others, or ```None```
+    /// if ```digests``` is empty. Ties go to the first digest.
+    ///
+    /// Fails with [`TxLshError::IncompatibleDigests`] if the digests were built with different
+    /// settings, as [`TxLsh::try_diff`].
+    pub fn medoid(digests: &[TxLsh], with_len: bool) -> Result<Option<usize>, TxLshError> {
+        let mut best: Option<(usize, usize)> = None;
+
+        for (ii, digest) in digests.iter().enumerate() {
+            let total = digests
+                .iter()
+                .map(|other| digest.try_diff(other, with_len))
+                .sum::<Result<usize, _>>()?;
+
+            // Option::is_none_or would need Rust 1.82.
+            #[allow(clippy::unnecessary_map_or)]
+            let better = best.map_or(true, |(_, min)| total < min);
+            if better {
+                best = Some((ii, total));
+            }
+        }
+
+        Ok(best.map(|(ii, _)| ii))
+    }
 }
 
 impl FromStr for TxLsh {
     type Err = TxLshError;
     /// Try to convert a hash string. Returns an instance of [`TxLsh`] if the conversion is successful.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (mut bucket_kind, mut checksum_kind, mut ver) = (None, None, None);
+        if !s.is_ascii() {
+            Err(TxLshError::InvalidHashValue)?
+        }
+
+        let ver = Version::from_prefix(s);
+        let (tag, tag_len) = Tag::decode(&s[ver.ver().len()..])?;
+
+        let (mut bucket_kind, mut checksum_kind) = (None, None);
 
         'outer: for bk in &BUCKETS_A {
             for ck in &CHECKSUM_A {
-                for v in &VERSION_A {
-                    if s.len() == hash_len(*bk, *ck, *v) {
-                        bucket_kind = Some(*bk);
-                        checksum_kind = Some(*ck);
-                        ver = Some(*v);
-                        break 'outer;
-                    }
+                if s.len() == hash_len(*bk, *ck, ver, &tag) {
+                    bucket_kind = Some(*bk);
+                    checksum_kind = Some(*ck);
+                    break 'outer;
                 }
             }
         }
@@ -117,23 +200,32 @@ impl FromStr for TxLsh {
             Err(TxLshError::InvalidHashValue)?
         }
 
-        let mut offset = ver.unwrap().ver().len();
+        let mut offset = ver.ver().len() + tag_len;
+        // from_str_radix accepts a leading sign, which the hex fields never have.
+        if !s[offset..].bytes().all(|b| b.is_ascii_hexdigit()) {
+            Err(TxLshError::ParseHexFailed)?
+        }
+
         let mut checksum = vec![0; checksum_kind.unwrap().checksum_len()];
-        let mut codes = vec![0; bucket_kind.unwrap().bucket_count() >> 2];
+        let mut codes = vec![0; code_len(bucket_kind.unwrap(), tag.quantile_kind)];
 
-        for ii in 0..checksum.len() {
-            checksum[ii] = u8::from_str_radix(
+        for item in checksum.iter_mut() {
+            *item = u8::from_str_radix(
                 &s[offset..(offset + 2)].chars().rev().collect::<String>(),
                 16,
             )?;
             offset += 2;
         }
 
+        let len_width = tag.length_kind.len_bytes() << 1;
         let len = usize::from_str_radix(
-            &s[offset..(offset + 2)].chars().rev().collect::<String>(),
+            &s[offset..(offset + len_width)]
+                .chars()
+                .rev()
+                .collect::<String>(),
             16,
         )?;
-        offset += 2;
+        offset += len_width;
 
         let qratio: usize = usize::from_str_radix(&s[offset..(offset + 2)], 16)?;
         offset += 2;
@@ -148,7 +240,8 @@ impl FromStr for TxLsh {
         Ok(Self {
             bucket_kind: bucket_kind.unwrap(),
             checksum_kind: checksum_kind.unwrap(),
-            ver: ver.unwrap(),
+            ver,
+            tag,
             checksum,
             len,
             q1ratio: qratio >> 4,
@@ -159,94 +252,401 @@ impl FromStr for TxLsh {
 }
 
 /// A builder struct for processing input stream(s).
+///
+/// The builder is generic over the [`TripletHasher`] mapping window triplets to buckets. By
+/// default, the hasher is selected at runtime from a [`Version`].
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub struct TxLshBuilder {
+pub struct TxLshBuilder<H: TripletHasher = Version> {
     bucket_kind: BucketKind,
     checksum_kind: ChecksumKind,
-    buckets: [u32; BUCKET_SIZE],
+    buckets: Buckets,
     bucket_count: usize,
     checksum: u8,
     checksum_array: Vec<u8>,
     checksum_len: usize,
-    code_size: usize,
-    data_len: usize,
-    slide_window: [u8; WINDOW_SIZE],
-    ver: Version,
+    data_len: u64,
+    /// Checksum state, position and preceding bytes, packed as a window, from which the checksum
+    /// still has to be computed after a [`TxLshBuilder::merge`].
+    checksum_pending: Option<(Vec<u8>, u64, u64)>,
+    hasher: H,
+    /// First bytes of the input, not part of any complete window.
+    head: [u8; MAX_WINDOW_SIZE],
+    length_kind: LengthKind,
+    min_size: MinSizePolicy,
+    slide_window: [u8; MAX_WINDOW_SIZE],
+    quantile_kind: QuantileKind,
+    run_filter: RunFilter,
+    /// Byte and length of the current run, when a [`RunFilter`] is set.
+    run: (u8, u64),
+    sampling: Option<Sampling>,
+    /// Whether data was processed in full after [`TxLshBuilder::update_sampled`].
+    unsampled: bool,
+    triplets: TripletSelection,
+    window_kind: WindowKind,
 }
 
 impl TxLshBuilder {
     /// Constructs a new builder based on the number of buckets, checksum length and version.
     pub fn new(bucket: BucketKind, checksum: ChecksumKind, ver: Version) -> Self {
+        Self::with_hasher(bucket, checksum, ver)
+    }
+}
+
+impl<H: TripletHasher> TxLshBuilder<H> {
+    /// Constructs a new builder based on the number of buckets, checksum length and hasher.
+    ///
+    /// The version of the digests is given by [`TripletHasher::version`].
+    pub fn with_hasher(bucket: BucketKind, checksum: ChecksumKind, hasher: H) -> Self {
         let bucket_count = bucket.bucket_count();
         let checksum_len = checksum.checksum_len();
 
         Self {
             bucket_kind: bucket,
             checksum_kind: checksum,
-            buckets: [0; BUCKET_SIZE],
+            buckets: Buckets::new(LengthKind::default()),
             bucket_count,
             checksum: 0,
             checksum_array: vec![0; checksum_len],
             checksum_len,
-            code_size: bucket_count >> 2,
             data_len: 0,
-            slide_window: [0; WINDOW_SIZE],
-            ver,
+            checksum_pending: None,
+            hasher,
+            head: [0; MAX_WINDOW_SIZE],
+            length_kind: LengthKind::default(),
+            min_size: MinSizePolicy::default(),
+            slide_window: [0; MAX_WINDOW_SIZE],
+            quantile_kind: QuantileKind::default(),
+            run_filter: RunFilter::default(),
+            run: (0, 0),
+            sampling: None,
+            unsampled: false,
+            triplets: TripletSelection::default_for(WindowKind::default()),
+            window_kind: WindowKind::default(),
+        }
+    }
+
+    /// Sets the size of the sliding window, discarding any data processed so far.
+    ///
+    /// The triplet selection is reset to the default one of the window. A window other than
+    /// [`WindowKind::Window5`] is recorded in the hash string, e.g. ```"T1W7"```.
+    pub fn with_window(mut self, window: WindowKind) -> Self {
+        self.reset();
+        self.window_kind = window;
+        self.triplets = TripletSelection::default_for(window);
+        self
+    }
+
+    /// Sets the triplets hashed for each byte, discarding any data processed so far.
+    ///
+    /// Fails if a position of the selection lies outside of the current window.
+    pub fn with_triplets(mut self, triplets: TripletSelection) -> Result<Self, TxLshError> {
+        if triplets.window_size() > self.window_kind.window_size() {
+            Err(TxLshError::InvalidTriplets)?
         }
+
+        self.reset();
+        self.triplets = triplets;
+        Ok(self)
+    }
+
+    /// Sets the number of quantiles each bucket is encoded against.
+    ///
+    /// Quantiles other than [`QuantileKind::Quartile`] are recorded in the hash string, e.g.
+    /// ```"T1Q3"``` for octiles.
+    pub fn with_quantiles(mut self, quantile: QuantileKind) -> Self {
+        self.quantile_kind = quantile;
+        self
+    }
+
+    /// Sets how the input length is captured in the digest.
+    ///
+    /// [`LengthKind::Extended`] is needed to hash inputs larger than 4GB, while
+    /// [`LengthKind::ShortText`] distinguishes lengths of short documents more finely. Both are
+    /// recorded in the hash string, e.g. ```"T1L1"```.
+    pub fn with_length(mut self, length: LengthKind) -> Self {
+        self.buckets = self.buckets.with_length(length);
+        self.length_kind = length;
+        self
+    }
+
+    /// Sets which inputs are long and varied enough to be hashed by [`TxLshBuilder::build`].
+    pub fn with_min_size(mut self, policy: MinSizePolicy) -> Self {
+        self.min_size = policy;
+        self
+    }
+
+    /// Sets how runs of a repeated byte are processed, discarding any data processed so far.
+    ///
+    /// The length captured in the digest is that of the filtered input. A filter other than
+    /// [`RunFilter::Off`] is recorded in the hash string, e.g. ```"T1R10010"``` for
+    /// ```RunFilter::Cap(16)```.
+    pub fn with_run_filter(mut self, filter: RunFilter) -> Self {
+        self.reset();
+        self.run_filter = filter;
+        self
     }
 
     /// Computes the quartiles and constructs the digest message and returns an instance of [`TxLsh`]
     /// that has all information needed to generate a hash value.
     pub fn build(&self) -> Result<TxLsh, TxLshError> {
-        if self.data_len < 50 {
-            Err(TxLshError::MinSizeNotReached)?
+        self.check_bound()?;
+        self.check_sampled()?;
+        if self.checksum_pending.is_some() {
+            Err(TxLshError::ChecksumPending)?
         }
 
-        let (q1, q2, q3) = find_quartiles(&self.buckets, self.bucket_count);
+        self.histogram().to_txlsh(self.bucket_kind)
+    }
 
-        if q3 == 0 {
-            Err(TxLshError::NoValidHash)?
+    /// Builds the digest into ```digest```, reusing its storage, e.g. to hash many inputs in a
+    /// loop without allocating.
+    ///
+    /// Builders with [`RunFilter::Skip`] holding back a run still allocate, as the run is
+    /// flushed into a copy of the builder. The digest is left untouched on failure.
+    pub fn build_into(&self, digest: &mut TxLsh) -> Result<(), TxLshError> {
+        self.check_bound()?;
+        self.check_sampled()?;
+        if self.checksum_pending.is_some() {
+            Err(TxLshError::ChecksumPending)?
         }
+        if let Some(flushed) = self.flushed() {
+            return flushed.build_into(digest);
+        }
+
+        // The checksum is copied straight from the builder rather than through the histogram.
+        self.histogram_with(Vec::new())
+            .to_txlsh_into(self.bucket_kind, digest)?;
+        digest.checksum.clear();
+        digest.checksum.extend_from_slice(self.checksum_slice());
+
+        Ok(())
+    }
 
-        let mut tmp = vec![0; self.code_size];
-        for ii in 0..self.code_size {
-            let mut h = 0;
+    /// Builds the digest of each input separately with the settings of the builder, in order,
+    /// ignoring any data processed so far.
+    ///
+    /// A single builder is reset between inputs, which saves most of the cost of hashing many
+    /// short inputs one by one. Inputs are hashed one after the other: hashing two or four of
+    /// them in lockstep, one byte of each in turn, measured 1 to 15% slower, as the windows of a
+    /// single input already keep the lookups busy and its buckets stay in cache.
+    pub fn build_batch<'a>(
+        &self,
+        inputs: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Vec<Result<TxLsh, TxLshError>> {
+        let mut builder = self.clone();
+        // Pearson tables are built once for the whole batch, however short each input is.
+        let mixer = self.mixer(usize::MAX);
 
-            for jj in 0..4 {
-                // Out of bound check?
-                let kk = self.buckets[4 * ii + jj];
-                if q3 < kk {
-                    h += 3 << (jj * 2);
-                } else if q2 < kk {
-                    h += 2 << (jj * 2);
-                } else if q1 < kk {
-                    h += 1 << (jj * 2);
+        inputs
+            .into_iter()
+            .map(|input| {
+                builder.reset();
+                if builder.run_filter == RunFilter::Off {
+                    builder.feed_mixer(&mixer, input);
+                } else {
+                    let filtered = builder.filter_runs(input);
+                    builder.feed_mixer(&mixer, &filtered);
                 }
+
+                builder.build()
+            })
+            .collect()
+    }
+
+    /// Builds the digest of each input stored one after the other in ```data```, the
+    /// ```ii```-th one spanning ```offsets[ii]..offsets[ii + 1]```, as
+    /// [`TxLshBuilder::build_batch`] does.
+    ///
+    /// Panics if the offsets decrease or exceed the length of ```data```.
+    pub fn build_batch_offsets(
+        &self,
+        data: &[u8],
+        offsets: &[usize],
+    ) -> Vec<Result<TxLsh, TxLshError>> {
+        self.build_batch(offsets.windows(2).map(|pair| &data[pair[0]..pair[1]]))
+    }
+
+    /// Returns the full bucket histogram of the data processed so far, from which digests of any
+    /// [`BucketKind`] can be built later.
+    ///
+    /// Its checksum is meaningless while a checksum is pending after a [`TxLshBuilder::merge`].
+    /// Unlike [`TxLshBuilder::build`], it does not check that the hasher is bound to a hash
+    /// function, see [`TxLshError::UnboundVersion`].
+    pub fn histogram(&self) -> TxLshHistogram {
+        match self.flushed() {
+            Some(flushed) => flushed.histogram(),
+            None => self.histogram_with(self.checksum_slice().to_vec()),
+        }
+    }
+
+    fn histogram_with(&self, checksum: Vec<u8>) -> TxLshHistogram {
+        TxLshHistogram {
+            buckets: self.buckets.to_wide(),
+            checksum_kind: self.checksum_kind,
+            checksum,
+            data_len: self.data_len,
+            min_size: self.min_size,
+            ver: self.hasher.version(),
+            tag: self.tag(),
+        }
+    }
+
+    /// Returns a copy of the builder having processed the run held back by [`RunFilter::Skip`],
+    /// if any.
+    fn flushed(&self) -> Option<Self> {
+        // A run short enough to be kept by RunFilter::Skip is only processed once it ends.
+        let (byte, run) = self.run;
+        if !matches!(self.run_filter, RunFilter::Skip(n) if run > 0 && run <= n as u64) {
+            return None;
+        }
+
+        let mut flushed = self.clone();
+        flushed.run = (0, 0);
+        flushed.feed_all(&vec![byte; run as usize]);
+
+        Some(flushed)
+    }
+
+    fn tag(&self) -> Tag {
+        Tag {
+            window_kind: self.window_kind,
+            triplets: (self.triplets.triplets() != self.window_kind.triplets())
+                .then(|| self.triplets.fingerprint()),
+            quantile_kind: self.quantile_kind,
+            length_kind: self.length_kind,
+            key: self.hasher.key_fingerprint(),
+            run_filter: self.run_filter,
+            sampling: self.sampling,
+        }
+    }
+
+    /// Serialises the state of the builder, so that hashing can be resumed later with
+    /// [`TxLshBuilder::restore`], e.g. after the process is restarted.
+    ///
+    /// The format starts with a format version and is stable across releases supporting it.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(BUCKET_SIZE + 64);
+        result.extend_from_slice(SNAPSHOT_MAGIC);
+        result.push(SNAPSHOT_VERSION);
+
+        let (ver, tag) = (self.hasher.version(), self.tag().encode());
+        result.push(ver.ver().len() as u8);
+        result.extend_from_slice(ver.ver().as_bytes());
+        result.push(tag.len() as u8);
+        result.extend_from_slice(tag.as_bytes());
+        result.push((self.bucket_kind.bucket_count() >> 7) as u8);
+
+        write_varint(&mut result, self.data_len);
+        result.push(self.checksum);
+        result.push(self.checksum_len as u8);
+        result.extend_from_slice(&self.checksum_array);
+        result.extend_from_slice(&self.slide_window);
+        result.extend_from_slice(&self.head);
+        result.push(self.run.0);
+        write_varint(&mut result, self.run.1);
+
+        match &self.checksum_pending {
+            None => result.push(0),
+            Some((checksum, pos, window)) => {
+                result.push(1);
+                result.extend_from_slice(checksum);
+                write_varint(&mut result, *pos);
+                result.extend_from_slice(&window.to_le_bytes());
             }
+        }
+        result.push(self.unsampled as u8);
 
-            tmp[ii] = h;
+        for count in self.buckets.to_wide() {
+            write_varint(&mut result, count);
         }
 
-        let len = l_capturing(self.data_len).unwrap();
-        let q1ratio = (((q1 as f64 * 100.) / (q3 as f64)) as usize) % 16;
-        let q2ratio = (((q2 as f64 * 100.) / (q3 as f64)) as usize) % 16;
+        result
+    }
 
-        let checksum = if self.checksum_len == 1 {
-            vec![self.checksum]
-        } else {
-            self.checksum_array.clone()
+    /// Restores a state serialised with [`TxLshBuilder::snapshot`], replacing any data processed
+    /// so far.
+    ///
+    /// The builder must have the same settings as the one the snapshot was taken from, otherwise
+    /// this fails with [`TxLshError::IncompatibleDigests`]. Malformed snapshots fail with
+    /// [`TxLshError::InvalidBytes`]. The builder is left unchanged on failure.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), TxLshError> {
+        let mut reader = ByteReader::new(snapshot);
+
+        if reader.bytes(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC
+            || reader.byte()? != SNAPSHOT_VERSION
+        {
+            Err(TxLshError::InvalidBytes)?
+        }
+
+        let len = reader.byte()? as usize;
+        let ver = reader.bytes(len)?;
+        let len = reader.byte()? as usize;
+        let tag = std::str::from_utf8(reader.bytes(len)?).map_err(|_| TxLshError::InvalidBytes)?;
+        let tag = match Tag::decode(tag)? {
+            (tag, read) if read == len => tag,
+            _ => Err(TxLshError::InvalidBytes)?,
         };
+        let bucket_count = (reader.byte()? as usize) << 7;
 
-        Ok(TxLsh {
-            bucket_kind: self.bucket_kind,
-            checksum_kind: self.checksum_kind,
-            ver: self.ver,
-            checksum,
-            len,
-            q1ratio,
-            q2ratio,
-            codes: tmp,
-        })
+        // Sampling is recorded by TxLshBuilder::update_sampled rather than set on the builder.
+        let settings = Tag {
+            sampling: tag.sampling,
+            ..self.tag()
+        };
+        if ver != self.hasher.version().ver().as_bytes()
+            || tag != settings
+            || bucket_count != self.bucket_kind.bucket_count()
+        {
+            Err(TxLshError::IncompatibleDigests)?
+        }
+
+        let data_len = reader.varint()?;
+        let checksum = reader.byte()?;
+        if reader.byte()? as usize != self.checksum_len {
+            Err(TxLshError::IncompatibleDigests)?
+        }
+        let checksum_array = reader.bytes(self.checksum_len)?.to_vec();
+        let slide_window = reader.bytes(MAX_WINDOW_SIZE)?.try_into().unwrap();
+        let head = reader.bytes(MAX_WINDOW_SIZE)?.try_into().unwrap();
+        let run = (reader.byte()?, reader.varint()?);
+
+        let checksum_pending = match reader.byte()? {
+            0 => None,
+            1 => Some((
+                reader.bytes(self.checksum_len)?.to_vec(),
+                reader.varint()?,
+                u64::from_le_bytes(reader.bytes(8)?.try_into().unwrap()),
+            )),
+            _ => Err(TxLshError::InvalidBytes)?,
+        };
+        let unsampled = match reader.byte()? {
+            0 => false,
+            1 if tag.sampling.is_some() => true,
+            _ => Err(TxLshError::InvalidBytes)?,
+        };
+
+        let mut buckets = [0; BUCKET_SIZE];
+        for count in buckets.iter_mut() {
+            *count = reader.varint()?;
+        }
+        let buckets = Buckets::from_wide(buckets, self.length_kind)?;
+
+        if !reader.is_empty() {
+            Err(TxLshError::InvalidBytes)?
+        }
+
+        self.buckets = buckets;
+        self.checksum = checksum;
+        self.checksum_array = checksum_array;
+        self.checksum_pending = checksum_pending;
+        self.data_len = data_len;
+        self.head = head;
+        self.run = run;
+        self.sampling = tag.sampling;
+        self.slide_window = slide_window;
+        self.unsampled = unsampled;
+
+        Ok(())
     }
 
     /// Processes an input stream.
@@ -261,169 +661,1732 @@ impl TxLshBuilder {
     /// * offset: index in array from which data will be read
     /// * len: number of bytes to be read
     pub fn update_from(&mut self, data: &[u8], offset: usize, len: usize) {
-        let mut j0 = self.data_len % WINDOW_SIZE;
-        let (mut j1, mut j2, mut j3, mut j4) = (
-            (j0 + WINDOW_SIZE - 1) % WINDOW_SIZE,
-            (j0 + WINDOW_SIZE - 2) % WINDOW_SIZE,
-            (j0 + WINDOW_SIZE - 3) % WINDOW_SIZE,
-            (j0 + WINDOW_SIZE - 4) % WINDOW_SIZE,
-        );
-
-        let mut fed_len = self.data_len;
-
-        for ii in offset..(offset + len) {
-            self.slide_window[j0] = data[ii];
-
-            if fed_len >= 4 {
-                self.checksum = hasher(
-                    0,
-                    self.slide_window[j0],
-                    self.slide_window[j1],
-                    self.checksum,
-                    self.ver,
-                );
-
-                if self.checksum_len > 1 {
-                    self.checksum_array[0] = self.checksum;
-
-                    for kk in 1..self.checksum_len {
-                        self.checksum_array[kk] = hasher(
-                            self.checksum_array[kk - 1],
-                            self.slide_window[j0],
-                            self.slide_window[j1],
-                            self.checksum_array[kk],
-                            self.ver,
-                        )
-                    }
-                }
+        let data = &data[offset..(offset + len)];
+        self.unsampled |= self.sampling.is_some() && !data.is_empty();
+
+        if self.run_filter == RunFilter::Off {
+            self.feed_all(data);
+        } else {
+            let filtered = self.filter_runs(data);
+            self.feed_all(&filtered);
+        }
+    }
+
+    /// Processes a whole stream, reporting progress and checking for cancellation between chunks.
+    ///
+    /// ```progress``` is called after each chunk with the number of bytes processed so far and
+    /// ```total```, the length of the stream if known. If the read fails or ```cancel``` is
+    /// cancelled, the builder is left as it was before the call and [`TxLshError::Io`] or
+    /// [`TxLshError::Cancelled`] is returned.
+    pub fn update_reader<R: Read>(
+        &mut self,
+        reader: &mut R,
+        total: Option<u64>,
+        mut progress: impl FnMut(u64, Option<u64>),
+        cancel: Option<&CancelToken>,
+    ) -> Result<(), TxLshError> {
+        let initial = self.clone();
+        let mut buffer = vec![0; READ_CHUNK];
+        let mut processed = 0;
 
-                // Select 6 triplets out of 10. The last four are processed in the next iteration.
-                // A  - B   - C  - D  - E
-                // j0   j1    j2   j3   j4
-
-                let mut r = hasher(
-                    2,
-                    self.slide_window[j0],
-                    self.slide_window[j1],
-                    self.slide_window[j2],
-                    self.ver,
-                );
-                self.buckets[r as usize] += 1;
-
-                r = hasher(
-                    3,
-                    self.slide_window[j0],
-                    self.slide_window[j1],
-                    self.slide_window[j3],
-                    self.ver,
-                );
-                self.buckets[r as usize] += 1;
-
-                r = hasher(
-                    5,
-                    self.slide_window[j0],
-                    self.slide_window[j2],
-                    self.slide_window[j3],
-                    self.ver,
-                );
-                self.buckets[r as usize] += 1;
-
-                r = hasher(
-                    7,
-                    self.slide_window[j0],
-                    self.slide_window[j2],
-                    self.slide_window[j4],
-                    self.ver,
-                );
-                self.buckets[r as usize] += 1;
-
-                r = hasher(
-                    11,
-                    self.slide_window[j0],
-                    self.slide_window[j1],
-                    self.slide_window[j4],
-                    self.ver,
-                );
-                self.buckets[r as usize] += 1;
-
-                r = hasher(
-                    13,
-                    self.slide_window[j0],
-                    self.slide_window[j3],
-                    self.slide_window[j4],
-                    self.ver,
-                );
-                self.buckets[r as usize] += 1;
+        let result = loop {
+            if cancel.is_some_and(|token| token.is_cancelled()) {
+                break Err(TxLshError::Cancelled);
             }
 
-            fed_len += 1;
+            match reader.read(&mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(len) => {
+                    self.update(&buffer[..len]);
+                    processed += len as u64;
+                    progress(processed, total);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => break Err(err.into()),
+            }
+        };
 
-            let tmp = j4;
-            j4 = j3;
-            j3 = j2;
-            j2 = j1;
-            j1 = j0;
-            j0 = tmp;
+        if result.is_err() {
+            *self = initial;
         }
 
-        self.data_len += len;
+        result
     }
 
-    /// Clears the state of a builder, removing all data.
-    pub fn reset(&mut self) {
-        self.buckets.fill(0);
-        self.checksum = 0;
-        self.data_len = 0;
-        self.slide_window.fill(0);
+    /// Processes a whole file, as [`TxLshBuilder::update_reader`] with the file size as total.
+    pub fn update_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        progress: impl FnMut(u64, Option<u64>),
+        cancel: Option<&CancelToken>,
+    ) -> Result<(), TxLshError> {
+        let mut file = File::open(path)?;
+        let total = file.metadata()?.len();
+
+        self.update_reader(&mut file, Some(total), progress, cancel)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Processes an input stream on up to ```threads``` threads, with the same result as
+    /// [`TxLshBuilder::update`].
+    ///
+    /// The input is split into one range per thread, whose builders are then combined as by
+    /// [`TxLshBuilder::merge`], while the checksum, which chains through every byte, is computed
+    /// on a thread of its own. Inputs processed with a run filter or sampling, while a checksum
+    /// is pending, or too short to be worth splitting, are processed on the calling thread.
+    ///
+    /// The checksum cannot be split, so the call takes at least one sequential pass of the
+    /// checksum over the input, which caps the speedup however many threads are used. The cap
+    /// was measured at about 4 for ```T1``` with a one-byte checksum and under 2 with a
+    /// three-byte one. ```X2``` hashes each window once for both buckets and checksum, so its
+    /// checksum costs almost as much as a sequential update and the cap is close to 1.
+    pub fn update_parallel(&mut self, data: &[u8], threads: usize)
+    where
+        H: Send + Sync,
+    {
+        let threads = threads.min(data.len() / MIN_PARALLEL_RANGE);
+        if threads < 2
+            || self.run_filter != RunFilter::Off
+            || self.sampling.is_some()
+            || self.checksum_pending.is_some()
+        {
+            return self.update(data);
+        }
 
-    static LOREM_0: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+        let mut template = self.clone();
+        template.reset();
+        let range = data.len().div_ceil(threads);
 
-    #[test]
-    fn test_tlsh_default() {
-        let lorem = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
-        let mut tlsh_default = TxLshBuilder::new(
-            BucketKind::Bucket128,
-            ChecksumKind::OneByte,
-            Version::Version4,
-        );
-        tlsh_default.update_from(LOREM_0, 0, lorem.len());
-        assert_eq!(
-            "T1DCF0DC36520C1B007FD32079B226559FD998A0200725E75AFCEAC99F5881184A4B1AA2",
-            tlsh_default.build().unwrap().hash()
-        )
+        let (parts, checksum) = std::thread::scope(|scope| {
+            let checksum = scope.spawn(|| {
+                let mut checksum = self.checksum_slice().to_vec();
+                let window = pack_window(&self.tail());
+                self.chain(&mut checksum, window, self.data_len, data.iter().copied());
+                checksum
+            });
+            let parts: Vec<_> = data
+                .chunks(range)
+                .map(|chunk| {
+                    scope.spawn(|| {
+                        let mut part = template.clone();
+                        part.update(chunk);
+                        part
+                    })
+                })
+                .collect();
+
+            (
+                parts
+                    .into_iter()
+                    .map(|part| part.join().unwrap())
+                    .collect::<Vec<_>>(),
+                checksum.join().unwrap(),
+            )
+        });
+
+        for part in &parts {
+            *self = Self::merge(self, part).expect("parts share the settings of the builder");
+        }
+        self.checksum_pending = None;
+        self.set_checksum(checksum);
     }
-    #[test]
-    fn test_tlsh_ungoliant() {
-        let lorem = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
-        let mut tlsh_ungoliant = TxLshBuilder::new(
-            BucketKind::Bucket256,
-            ChecksumKind::ThreeByte,
-            Version::Version4,
-        );
-        tlsh_ungoliant.update_from(LOREM_0, 0, lorem.len());
-        assert_eq!(
-            "T1DC33D4F0DCA405C02AF1D4860CA5894A05301D60E9915198060A7044C608A1E89A11BD2B2836520C1B007FD32079B226559FD998A0200725E75AFCEAC99F5881184A4B1AA2",
-            tlsh_ungoliant.build().unwrap().hash()
-        )
+
+    /// Processes a whole file on up to ```threads``` threads, as
+    /// [`TxLshBuilder::update_parallel`], reading it through a memory map.
+    ///
+    /// The file must not be modified while it is processed. Requires the ```mmap``` feature.
+    #[cfg(feature = "mmap")]
+    pub fn update_file_parallel<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        threads: usize,
+    ) -> Result<(), TxLshError>
+    where
+        H: Send + Sync,
+    {
+        let file = File::open(path)?;
+        // Safety: the map is only read, and the file is left unmodified by contract.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        self.update_parallel(&map, threads);
+
+        Ok(())
     }
-    #[test]
-    fn test_txlsh() {
-        let lorem = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
-        let mut txlsh = TxLshBuilder::new(
-            BucketKind::Bucket256,
-            ChecksumKind::ThreeByte,
-            Version::TxLshV1,
+
+    /// Processes a sample of the blocks of a whole stream, from its start to its end, instead of
+    /// every byte.
+    ///
+    /// The blocks are processed as one contiguous input, so the length captured in the digest is
+    /// that of the sample. The sampling parameters are recorded in the hash string, e.g.
+    /// ```"T1P0C00080007"``` for blocks of 4KB, a ratio of 1/8 and a seed of 7, so that the digest
+    /// is only compared with digests sampled the same way.
+    ///
+    /// The builder must hold no data, otherwise this fails with [`TxLshError::InvalidSampling`]
+    /// rather than mark data processed in full as sampled. Likewise, building fails with
+    /// [`TxLshError::InvalidSampling`] once more data is processed in full after the sample. If a
+    /// read or seek fails, the builder is left as it was before the call.
+    pub fn update_sampled<R: Read + Seek>(
+        &mut self,
+        reader: &mut R,
+        sampling: Sampling,
+    ) -> Result<(), TxLshError> {
+        if self.data_len > 0 || self.run.1 > 0 {
+            Err(TxLshError::InvalidSampling)?
+        }
+
+        let initial = self.clone();
+        let result = self.update_blocks(reader, sampling);
+
+        match result {
+            Ok(()) => self.sampling = Some(sampling),
+            Err(_) => *self = initial,
+        }
+
+        result
+    }
+
+    fn update_blocks<R: Read + Seek>(
+        &mut self,
+        reader: &mut R,
+        sampling: Sampling,
+    ) -> Result<(), TxLshError> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        let mut block = vec![0; sampling.block_size() as usize];
+
+        for offset in sampling.block_offsets(len) {
+            let block_len = sampling.block_size().min(len - offset) as usize;
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.read_exact(&mut block[..block_len])?;
+            self.update(&block[..block_len]);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the bytes of ```data``` kept by the run filter, updating the current run.
+    fn filter_runs(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(data.len());
+        let max_run = self.run_filter.max_run() as u64;
+        let (mut current, mut run) = self.run;
+
+        for &byte in data {
+            if run > 0 && byte == current {
+                run += 1;
+            } else {
+                // The previous run ended short enough to be kept.
+                if matches!(self.run_filter, RunFilter::Skip(_)) && run <= max_run {
+                    result.resize(result.len() + run as usize, current);
+                }
+
+                (current, run) = (byte, 1);
+            }
+
+            if matches!(self.run_filter, RunFilter::Cap(_)) && run <= max_run {
+                result.push(byte);
+            }
+        }
+
+        self.run = (current, run);
+        result
+    }
+
+    fn feed_all(&mut self, data: &[u8]) {
+        let mixer = self.mixer(data.len());
+        self.feed_mixer(&mixer, data);
+    }
+
+    /// Resolves the hasher into the mixer fed ```len``` bytes, precomputing Pearson tables if it
+    /// pays off.
+    fn mixer(&self, len: usize) -> AnyMixer<H> {
+        let triplets = self.triplets.triplets();
+
+        match self.hasher.resolve() {
+            Resolved::Pearson(hasher)
+                if len >= PearsonTables::MIN_LEN
+                    && triplets.len() <= PearsonTables::MAX_TRIPLETS =>
+            {
+                AnyMixer::Tables(PearsonTables::new(&hasher, triplets))
+            }
+            Resolved::Pearson(hasher) => AnyMixer::Pearson(hasher),
+            Resolved::Wide(hasher) => AnyMixer::Wide(WideXxh3::new(&hasher, self.window_size())),
+            Resolved::Xxh3(hasher) => AnyMixer::Xxh3(hasher),
+            Resolved::Custom(hasher) => AnyMixer::Custom(hasher),
+        }
+    }
+
+    fn feed_mixer(&mut self, mixer: &AnyMixer<H>, data: &[u8]) {
+        match mixer {
+            AnyMixer::Tables(tables) => self.feed(tables, data),
+            AnyMixer::Pearson(hasher) => self.feed(&Plain(hasher), data),
+            AnyMixer::Wide(wide) => self.feed(wide, data),
+            AnyMixer::Xxh3(hasher) => self.feed(hasher, data),
+            AnyMixer::Custom(hasher) => self.feed(&Plain(hasher), data),
+        }
+    }
+
+    fn feed<M: Mixer>(&mut self, mixer: &M, data: &[u8]) {
+        match self.checksum_len {
+            1 => self.feed_with::<M, 1>(mixer, data),
+            _ => self.feed_with::<M, 3>(mixer, data),
+        }
+    }
+
+    /// Processes ```data``` with a checksum of ```N``` bytes, indexing the input directly instead
+    /// of going through the sliding window.
+    fn feed_with<M: Mixer, const N: usize>(&mut self, mixer: &M, data: &[u8]) {
+        let window = self.window_kind.window_size();
+        let mut checksum = [self.checksum; N];
+        if N > 1 {
+            checksum.copy_from_slice(&self.checksum_array);
+        }
+
+        // Windows spanning the end of the previous input and the start of this one.
+        let mut boundary = [0; 2 * MAX_WINDOW_SIZE];
+        let prev = (self.data_len.min(window as u64 - 1)) as usize;
+        let next = data.len().min(window - 1);
+        for (item, pos) in boundary
+            .iter_mut()
+            .zip((self.data_len - prev as u64)..self.data_len)
+        {
+            *item = self.slide_window[(pos % window as u64) as usize];
+        }
+        boundary[prev..(prev + next)].copy_from_slice(&data[..next]);
+        self.scan(
+            mixer,
+            &boundary[..(prev + next)],
+            prev.max(window - 1),
+            &mut checksum,
+        );
+
+        // Windows lying within this input.
+        self.scan(mixer, data, window - 1, &mut checksum);
+
+        self.checksum = checksum[0];
+        if N > 1 {
+            self.checksum_array.copy_from_slice(&checksum);
+        }
+
+        for (pos, &byte) in (self.data_len..(window as u64 - 1)).zip(data) {
+            self.head[pos as usize] = byte;
+        }
+
+        let start = data.len().saturating_sub(window);
+        for (pos, &byte) in ((self.data_len + start as u64)..).zip(&data[start..]) {
+            self.slide_window[(pos % window as u64) as usize] = byte;
+        }
+
+        self.data_len += data.len() as u64;
+    }
+
+    /// Hashes every window of ```data``` ending at index ```from``` or later.
+    fn scan<M: Mixer, const N: usize>(
+        &mut self,
+        mixer: &M,
+        data: &[u8],
+        from: usize,
+        checksum: &mut [u8; N],
+    ) {
+        let (kind, triplets) = (self.window_kind, self.triplets.triplets());
+
+        match &mut self.buckets {
+            Buckets::Narrow(buckets) => {
+                scan_kind(buckets, mixer, kind, triplets, data, from, checksum)
+            }
+            Buckets::Wide(buckets) => {
+                scan_kind(buckets, mixer, kind, triplets, data, from, checksum)
+            }
+        }
+    }
+
+    /// Combines the states of two builders fed with consecutive ranges of an input.
+    ///
+    /// Buckets, length and the triplets spanning both ranges are combined exactly. The checksum is
+    /// a chain through every byte of the input, so its value over ```right``` depends on the state
+    /// ```left``` ends in. The Pearson checksums permute that state at every byte, so ```right```
+    /// could only be summarised by its result for every possible state. The checksum stays pending
+    /// over the range of ```right``` until that range is passed to
+    /// [`TxLshBuilder::continue_checksum`], which costs one hash per byte and checksum byte instead
+    /// of one per triplet. Only then is the builder identical to one fed the whole input, and until
+    /// then [`TxLshBuilder::build`] fails with [`TxLshError::ChecksumPending`]. If ```left``` holds
+    /// no data, the checksum of ```right``` is already that of the input and nothing is left
+    /// pending.
+    ///
+    /// Fails with [`TxLshError::IncompatibleDigests`] if the builders have different settings, or
+    /// use a run filter or sampling.
+    pub fn merge(left: &Self, right: &Self) -> Result<Self, TxLshError> {
+        let window = left.window_kind.window_size();
+
+        if !left.same_settings(right)
+            || left.run_filter != RunFilter::Off
+            || left.sampling.is_some()
+            || right.sampling.is_some()
+        {
+            Err(TxLshError::IncompatibleDigests)?
+        }
+
+        let mut merged = left.clone();
+        merged.buckets.add(&right.buckets);
+
+        // Windows ending in the first bytes of right also span the last bytes of left.
+        let boundary = [left.tail(), right.head().to_vec()].concat();
+        let first_end = boundary.len().min(left.tail().len().max(window - 1));
+        merged.count_windows(&boundary[(first_end + 1).saturating_sub(window)..], false);
+
+        merged.data_len = left.data_len + right.data_len;
+        if left.data_len < window as u64 - 1 {
+            let len = boundary.len().min(window - 1);
+            merged.head[..len].copy_from_slice(&boundary[..len]);
+        }
+
+        let tail = if right.data_len >= window as u64 - 1 {
+            right.tail()
+        } else {
+            boundary[boundary.len().saturating_sub(window - 1)..].to_vec()
+        };
+        for (pos, &byte) in (merged.data_len - tail.len() as u64..).zip(&tail) {
+            merged.slide_window[(pos % window as u64) as usize] = byte;
+        }
+
+        if left.data_len == 0 {
+            merged.checksum = right.checksum;
+            merged.checksum_array.clone_from(&right.checksum_array);
+            merged.checksum_pending.clone_from(&right.checksum_pending);
+        } else if right.data_len > 0 && merged.checksum_pending.is_none() {
+            let window = pack_window(&left.tail());
+            merged.checksum_pending = Some((left.checksum_slice().to_vec(), left.data_len, window));
+        }
+
+        Ok(merged)
+    }
+
+    /// Computes a checksum left pending by [`TxLshBuilder::merge`] over ```data```, the bytes of
+    /// the input following those already covered by the checksum. It may be called repeatedly
+    /// with consecutive ranges.
+    pub fn continue_checksum(&mut self, data: &[u8]) {
+        let Some((mut checksum, pos, window)) = self.checksum_pending.take() else {
+            return;
+        };
+
+        let data = &data[..data.len().min((self.data_len - pos) as usize)];
+        let window = self.chain(&mut checksum, window, pos, data.iter().copied());
+        let pos = pos + data.len() as u64;
+
+        if pos < self.data_len {
+            self.checksum_pending = Some((checksum, pos, window));
+        } else {
+            self.set_checksum(checksum);
+        }
+    }
+
+    fn set_checksum(&mut self, checksum: Vec<u8>) {
+        self.checksum = checksum[0];
+        if self.checksum_len > 1 {
+            self.checksum_array = checksum;
+        }
+    }
+
+    fn same_settings(&self, other: &Self) -> bool {
+        self.bucket_kind == other.bucket_kind
+            && self.checksum_kind == other.checksum_kind
+            && self.hasher.version() == other.hasher.version()
+            && self.hasher.key_fingerprint() == other.hasher.key_fingerprint()
+            && self.length_kind == other.length_kind
+            && self.min_size == other.min_size
+            && self.quantile_kind == other.quantile_kind
+            && self.run_filter == other.run_filter
+            && self.triplets == other.triplets
+            && self.window_kind == other.window_kind
+    }
+
+    /// Returns the first bytes of the input that precede the first complete window.
+    fn head(&self) -> &[u8] {
+        let window = self.window_kind.window_size() as u64;
+        &self.head[..(self.data_len.min(window - 1) as usize)]
+    }
+
+    /// Returns the last bytes of the input that will be part of the next windows.
+    fn tail(&self) -> Vec<u8> {
+        let window = self.window_kind.window_size() as u64;
+        (self.data_len.saturating_sub(window - 1)..self.data_len)
+            .map(|pos| self.slide_window[(pos % window) as usize])
+            .collect()
+    }
+
+    fn checksum_slice(&self) -> &[u8] {
+        if self.checksum_len == 1 {
+            std::slice::from_ref(&self.checksum)
+        } else {
+            &self.checksum_array
+        }
+    }
+
+    /// Removes the triplets of every window ending in ```data```, which holds the oldest bytes
+    /// processed so far.
+    pub(crate) fn remove_windows(&mut self, data: &[u8]) {
+        self.count_windows(data, true);
+    }
+
+    fn count_windows(&mut self, data: &[u8], removed: bool) {
+        match self.hasher.resolve() {
+            Resolved::Pearson(hasher) => self.count(&Plain(&hasher), data, removed),
+            Resolved::Wide(hasher) => {
+                self.count(&WideXxh3::new(&hasher, self.window_size()), data, removed)
+            }
+            Resolved::Xxh3(hasher) => self.count(&hasher, data, removed),
+            Resolved::Custom(hasher) => self.count(&Plain(&hasher), data, removed),
+        }
+    }
+
+    /// Counts the triplets of every window ending in ```data``` in or out of the buckets.
+    fn count<M: Mixer>(&mut self, mixer: &M, data: &[u8], removed: bool) {
+        let window = self.window_kind.window_size();
+
+        for end in (window - 1)..data.len() {
+            let packed = pack_window(&data[(end + 1 - window)..=end]);
+
+            mixer.buckets(packed, self.triplets.triplets(), |r| {
+                self.buckets.count(r as usize, removed)
+            });
+        }
+    }
+
+    /// Computes the checksum of ```data``` alone, as [`TxLshBuilder::update`] would.
+    pub(crate) fn checksum_of(&self, data: impl Iterator<Item = u8>) -> Vec<u8> {
+        let mut checksum = vec![0; self.checksum_len];
+        self.chain(&mut checksum, 0, 0, data);
+
+        checksum
+    }
+
+    /// Continues the checksum chain over ```data```, starting at position ```pos``` of the input
+    /// after the bytes packed in ```window```. Returns the window packing the last bytes.
+    fn chain(
+        &self,
+        checksum: &mut [u8],
+        window: u64,
+        pos: u64,
+        data: impl Iterator<Item = u8>,
+    ) -> u64 {
+        match self.hasher.resolve() {
+            Resolved::Pearson(hasher) => {
+                self.chain_with(&Plain(&hasher), checksum, window, pos, data)
+            }
+            Resolved::Wide(hasher) => {
+                let mixer = WideXxh3::new(&hasher, self.window_size());
+                self.chain_with(&mixer, checksum, window, pos, data)
+            }
+            Resolved::Xxh3(hasher) => self.chain_with(&hasher, checksum, window, pos, data),
+            Resolved::Custom(hasher) => {
+                self.chain_with(&Plain(&hasher), checksum, window, pos, data)
+            }
+        }
+    }
+
+    fn chain_with<M: Mixer>(
+        &self,
+        mixer: &M,
+        checksum: &mut [u8],
+        mut window: u64,
+        pos: u64,
+        data: impl Iterator<Item = u8>,
+    ) -> u64 {
+        let size = self.window_kind.window_size() as u64;
+
+        for (fed_len, byte) in (pos..).zip(data) {
+            window = (window << 8) | byte as u64;
+
+            if fed_len >= size - 1 {
+                mixer.checksum(window, checksum);
+            }
+        }
+
+        window
+    }
+
+    /// Fails if the hasher has no hash function of its own, see [`TxLshError::UnboundVersion`].
+    pub(crate) fn check_bound(&self) -> Result<(), TxLshError> {
+        if is_unbound(&self.hasher) {
+            Err(TxLshError::UnboundVersion)?
+        }
+
+        Ok(())
+    }
+
+    /// Fails if data was processed in full after a sample, which the digest would mark as
+    /// sampled.
+    pub(crate) fn check_sampled(&self) -> Result<(), TxLshError> {
+        if self.unsampled {
+            Err(TxLshError::InvalidSampling)?
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn bucket_kind(&self) -> BucketKind {
+        self.bucket_kind
+    }
+
+    pub(crate) fn window_size(&self) -> usize {
+        self.window_kind.window_size()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn buckets(&self) -> [u64; BUCKET_SIZE] {
+        self.buckets.to_wide()
+    }
+
+    /// Clears the state of a builder, removing all data.
+    pub fn reset(&mut self) {
+        self.buckets = Buckets::new(self.length_kind);
+        self.checksum = 0;
+        self.checksum_array.fill(0);
+        self.checksum_pending = None;
+        self.data_len = 0;
+        self.head.fill(0);
+        self.run = (0, 0);
+        self.sampling = None;
+        self.slide_window.fill(0);
+        self.unsampled = false;
+    }
+}
+
+/// Bucket counts of a builder. Counts are 64-bit only with [`LengthKind::Extended`], whose
+/// larger array is boxed so that other builders keep the size of the reference implementation.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Buckets {
+    Narrow([u32; BUCKET_SIZE]),
+    Wide(Box<[u64; BUCKET_SIZE]>),
+}
+
+impl Buckets {
+    fn new(length: LengthKind) -> Self {
+        match length {
+            LengthKind::Extended => Buckets::Wide(Box::new([0; BUCKET_SIZE])),
+            _ => Buckets::Narrow([0; BUCKET_SIZE]),
+        }
+    }
+
+    /// Stores 64-bit counts with the width used for ```length```, failing with
+    /// [`TxLshError::InvalidBytes`] if they do not fit.
+    fn from_wide(counts: [u64; BUCKET_SIZE], length: LengthKind) -> Result<Self, TxLshError> {
+        match Self::new(length) {
+            Buckets::Wide(_) => Ok(Buckets::Wide(Box::new(counts))),
+            Buckets::Narrow(mut narrow) => {
+                for (count, &wide) in narrow.iter_mut().zip(&counts) {
+                    *count = u32::try_from(wide).map_err(|_| TxLshError::InvalidBytes)?;
+                }
+
+                Ok(Buckets::Narrow(narrow))
+            }
+        }
+    }
+
+    /// Returns the same counts with the width used for ```length```. Counts beyond 32 bits
+    /// saturate.
+    fn with_length(&self, length: LengthKind) -> Self {
+        let counts = self.to_wide();
+        Self::from_wide(counts, length)
+            .unwrap_or_else(|_| Buckets::Narrow(counts.map(|c| c.min(u32::MAX as u64) as u32)))
+    }
+
+    fn to_wide(&self) -> [u64; BUCKET_SIZE] {
+        match self {
+            Buckets::Narrow(counts) => counts.map(u64::from),
+            Buckets::Wide(counts) => **counts,
+        }
+    }
+
+    fn count(&mut self, bucket: usize, removed: bool) {
+        match (self, removed) {
+            (Buckets::Narrow(counts), false) => counts[bucket] += 1,
+            (Buckets::Narrow(counts), true) => counts[bucket] -= 1,
+            (Buckets::Wide(counts), false) => counts[bucket] += 1,
+            (Buckets::Wide(counts), true) => counts[bucket] -= 1,
+        }
+    }
+
+    /// Adds the counts of ```other```, which has the same width as builders with the same
+    /// settings do.
+    fn add(&mut self, other: &Self) {
+        match (self, other) {
+            (Buckets::Narrow(counts), Buckets::Narrow(added)) => add_counts(counts, added),
+            (Buckets::Wide(counts), Buckets::Wide(added)) => add_counts(&mut **counts, &**added),
+            _ => unreachable!("merged builders share their length kind"),
+        }
+    }
+}
+
+fn add_counts<T: Copy + AddAssign>(counts: &mut [T], added: &[T]) {
+    for (count, &added) in counts.iter_mut().zip(added) {
+        *count += added;
+    }
+}
+
+/// A hasher resolved into the [`Mixer`] fed by a builder.
+#[allow(clippy::large_enum_variant)]
+enum AnyMixer<H> {
+    Tables(PearsonTables),
+    Pearson(Pearson),
+    Wide(WideXxh3),
+    Xxh3(Xxh3),
+    Custom(H),
+}
+
+/// Hashes every window of ```data``` ending at index ```from``` or later, with the triplets of a
+/// builder with the sliding window ```kind```.
+fn scan_kind<T: Copy + AddAssign + From<u8>, M: Mixer, const N: usize>(
+    buckets: &mut [T; BUCKET_SIZE],
+    mixer: &M,
+    kind: WindowKind,
+    triplets: &[(u8, usize, usize, usize)],
+    data: &[u8],
+    from: usize,
+    checksum: &mut [u8; N],
+) {
+    let mut scan = |triplets| scan_windows(buckets, mixer, triplets, data, from, checksum);
+
+    // Default selections are passed as constants, so that the loop over the triplets is
+    // unrolled with constant positions.
+    match kind {
+        kind if kind.triplets() != triplets => scan(triplets),
+        WindowKind::Window4 => scan(WindowKind::Window4.triplets()),
+        WindowKind::Window5 => scan(WindowKind::Window5.triplets()),
+        WindowKind::Window6 => scan(WindowKind::Window6.triplets()),
+        WindowKind::Window7 => scan(WindowKind::Window7.triplets()),
+        WindowKind::Window8 => scan(WindowKind::Window8.triplets()),
+    }
+}
+
+/// Hashes the checksum and triplets of every window of ```data``` ending at index ```from``` or
+/// later.
+#[inline(always)]
+fn scan_windows<T: Copy + AddAssign + From<u8>, M: Mixer, const N: usize>(
+    buckets: &mut [T; BUCKET_SIZE],
+    mixer: &M,
+    triplets: &[(u8, usize, usize, usize)],
+    data: &[u8],
+    from: usize,
+    checksum: &mut [u8; N],
+) {
+    if from >= data.len() {
+        return;
+    }
+
+    let mut window = pack_window(&data[..from]);
+    for &newest in &data[from..] {
+        window = (window << 8) | newest as u64;
+        mixer.window(window, triplets, checksum, |r| {
+            buckets[r as usize] += T::from(1)
+        });
+    }
+}
+
+/// Packs the last bytes of ```data``` in a window, the newest byte in the lowest one.
+fn pack_window(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0u64, |window, &byte| (window << 8) | byte as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "mmap")]
+    use crate::hash_file_parallel;
+    use crate::hash_funcs::{pearson_h2, TripletMixer};
+    use crate::hash_parallel;
+    use crate::{Pearson, Xxh3};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// An allocator counting the allocations of each thread, as tests run concurrently.
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    static LOREM_0: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+
+    #[test]
+    fn test_tlsh_default() {
+        let lorem = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+        let mut tlsh_default = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        tlsh_default.update_from(LOREM_0, 0, lorem.len());
+        assert_eq!(
+            "T1DCF0DC36520C1B007FD32079B226559FD998A0200725E75AFCEAC99F5881184A4B1AA2",
+            tlsh_default.build().unwrap().hash()
+        )
+    }
+    #[test]
+    fn test_tlsh_ungoliant() {
+        let lorem = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+        let mut tlsh_ungoliant = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::Version4,
+        );
+        tlsh_ungoliant.update_from(LOREM_0, 0, lorem.len());
+        assert_eq!(
+            "T1DC33D4F0DCA405C02AF1D4860CA5894A05301D60E9915198060A7044C608A1E89A11BD2B2836520C1B007FD32079B226559FD998A0200725E75AFCEAC99F5881184A4B1AA2",
+            tlsh_ungoliant.build().unwrap().hash()
+        )
+    }
+    #[test]
+    fn test_txlsh() {
+        let lorem = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+        let mut txlsh = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV1,
+        );
+        txlsh.update_from(LOREM_0, 0, lorem.len());
+        assert_eq!(
+            "X18B6AADF05C1C6293150EE83C25635D4C68650291D7C57D492757E52174B7800D6577546B39F325196422CA6DA78F6553446016F5B138B8F8B97410A0D3930ACD3FBCB99991",
+            txlsh.build().unwrap().hash()
+        )
+    }
+
+    #[test]
+    fn test_window_size() {
+        let mut default = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        let mut window5 = default.clone().with_window(WindowKind::Window5);
+        let mut window8 = default.clone().with_window(WindowKind::Window8);
+        default.update(LOREM_0);
+        window5.update(LOREM_0);
+        window8.update(LOREM_0);
+
+        assert_eq!(default.build().unwrap(), window5.build().unwrap());
+
+        let hash = window8.build().unwrap().hash();
+        assert!(hash.starts_with("T1W8"));
+        assert_eq!(hash, TxLsh::from_str(&hash).unwrap().hash());
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let hash = "X18B6AADF05C1C6293150EE83C25635D4C68650291D7C57D492757E52174B7800D6577546B39F325196422CA6DA78F6553446016F5B138B8F8B97410A0D3930ACD3FBCB99991";
+        let txlsh = TxLsh::from_str(hash).unwrap();
+        assert_eq!(Version::TxLshV1, txlsh.ver);
+        assert_eq!(hash, txlsh.hash());
+    }
+
+    #[test]
+    fn test_triplet_selection() {
+        let default = TripletSelection::default_for(WindowKind::Window5);
+        let mut explicit = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV1,
+        )
+        .with_triplets(TripletSelection::new(default.triplets()).unwrap())
+        .unwrap();
+        explicit.update(LOREM_0);
+        assert_eq!(
+            "X18B6AADF05C1C6293150EE83C25635D4C68650291D7C57D492757E52174B7800D6577546B39F325196422CA6DA78F6553446016F5B138B8F8B97410A0D3930ACD3FBCB99991",
+            explicit.build().unwrap().hash()
+        );
+
+        let dense = TripletSelection::new(&[
+            (2, 0, 1, 2),
+            (3, 0, 1, 3),
+            (5, 0, 2, 3),
+            (7, 0, 2, 4),
+            (11, 0, 1, 4),
+            (13, 0, 3, 4),
+            (17, 1, 2, 3),
+            (19, 1, 2, 4),
+        ])
+        .unwrap();
+        let mut custom = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        )
+        .with_triplets(dense)
+        .unwrap();
+        custom.update(LOREM_0);
+        let hash = custom.build().unwrap().hash();
+        assert!(hash.starts_with("T1S"));
+        assert_eq!(hash, TxLsh::from_str(&hash).unwrap().hash());
+
+        let wide = TripletSelection::new(&[(2, 0, 1, 6)]).unwrap();
+        assert!(TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4
+        )
+        .with_triplets(wide)
+        .is_err());
+        assert!(TripletSelection::new(&[(2, 0, 1, 8)]).is_err());
+    }
+
+    #[test]
+    fn test_quantiles() {
+        let mut quartile = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::Version4,
+        );
+        let mut octile = quartile.clone().with_quantiles(QuantileKind::Octile);
+        let mut hexadecile = quartile.clone().with_quantiles(QuantileKind::Hexadecile);
+        quartile.update(LOREM_0);
+        octile.update(LOREM_0);
+        hexadecile.update(LOREM_0);
+
+        assert_eq!(
+            quartile.build().unwrap(),
+            quartile
+                .clone()
+                .with_quantiles(QuantileKind::Quartile)
+                .build()
+                .unwrap()
+        );
+
+        for builder in [octile, hexadecile] {
+            let txlsh = builder.build().unwrap();
+            let hash = txlsh.hash();
+            assert!(hash.starts_with(&format!("T1Q{}", builder.quantile_kind.code_bits())));
+
+            let parsed = TxLsh::from_str(&hash).unwrap();
+            assert_eq!(txlsh, parsed);
+            assert_eq!(0, txlsh.diff(&parsed, true));
+        }
+    }
+
+    #[test]
+    fn test_min_size_policy() {
+        let short = &LOREM_0[..20];
+        let builder = |policy| {
+            let mut builder = TxLshBuilder::new(
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Version4,
+            )
+            .with_min_size(policy);
+            builder.update(short);
+            builder.build()
+        };
+
+        assert!(matches!(
+            builder(MinSizePolicy::Standard),
+            Err(TxLshError::MinSizeNotReached)
+        ));
+        assert!(builder(MinSizePolicy::Force).is_ok());
+        assert!(builder(MinSizePolicy::Custom {
+            min_len: 20,
+            min_buckets: 0
+        })
+        .is_ok());
+        assert!(matches!(
+            builder(MinSizePolicy::Custom {
+                min_len: 20,
+                min_buckets: 128
+            }),
+            Err(TxLshError::MinBucketsNotReached)
+        ));
+
+        let mut conservative = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        )
+        .with_min_size(MinSizePolicy::Conservative);
+        conservative.update(LOREM_0);
+        assert_eq!(
+            "T1DCF0DC36520C1B007FD32079B226559FD998A0200725E75AFCEAC99F5881184A4B1AA2",
+            conservative.build().unwrap().hash()
+        );
+        conservative.reset();
+        conservative.update(&LOREM_0[..200]);
+        assert!(matches!(
+            conservative.build(),
+            Err(TxLshError::MinSizeNotReached)
+        ));
+    }
+
+    #[test]
+    fn test_extended_length() {
+        let mut standard = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::Version4,
+        );
+        let mut extended = standard.clone().with_length(LengthKind::Extended);
+        standard.update(LOREM_0);
+        extended.update(LOREM_0);
+
+        let hash = extended.build().unwrap().hash();
+        assert!(hash.starts_with("T1L1"));
+        assert_eq!(hash, TxLsh::from_str(&hash).unwrap().hash());
+
+        // Only the length field differs, but digests of different length kinds are not compared.
+        let (x, y) = (standard.build().unwrap(), extended.build().unwrap());
+        assert_eq!(x.codes, y.codes);
+        assert!(matches!(
+            x.try_diff(&y, true),
+            Err(TxLshError::IncompatibleDigests)
+        ));
+
+        // Only extended builders count in 64 bits.
+        assert!(matches!(standard.buckets, Buckets::Narrow(_)));
+        assert!(matches!(extended.buckets, Buckets::Wide(_)));
+        assert_eq!(standard.buckets(), extended.buckets());
+
+        standard.data_len = 5_000_000_000;
+        extended.data_len = 5_000_000_000;
+        assert!(matches!(standard.build(), Err(TxLshError::DataLenOverflow)));
+        assert!(extended.build().is_ok());
+    }
+
+    #[test]
+    fn test_short_text_length() {
+        let short_text = |len| {
+            let mut builder = TxLshBuilder::new(
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Version4,
+            )
+            .with_length(LengthKind::ShortText);
+            builder.update(&LOREM_0[..len]);
+            builder.build().unwrap()
+        };
+
+        let (full, cut) = (short_text(LOREM_0.len()), short_text(LOREM_0.len() - 20));
+        let hash = full.hash();
+        assert!(hash.starts_with("T1L2"));
+        assert_eq!(full, TxLsh::from_str(&hash).unwrap());
+        assert_eq!(full.diff(&cut, false) + 1, full.diff(&cut, true));
+    }
+
+    #[derive(Clone)]
+    struct Fnv;
+
+    impl TripletHasher for Fnv {
+        fn hash(&self, salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
+            [salt, ii, jj, kk].iter().fold(0x811c9dc5u32, |h, &b| {
+                (h ^ b as u32).wrapping_mul(0x01000193)
+            }) as u8
+        }
+
+        fn version(&self) -> Version {
+            Version::custom("Z1").unwrap()
+        }
+    }
+
+    #[test]
+    fn test_triplet_hasher() {
+        let mut runtime = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV1,
+        );
+        let mut pearson = TxLshBuilder::with_hasher(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Pearson::default(),
+        );
+        let mut xxh3 = TxLshBuilder::with_hasher(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Xxh3::default(),
+        );
+        runtime.update(LOREM_0);
+        pearson.update(LOREM_0);
+        xxh3.update(LOREM_0);
+
+        assert_eq!(runtime.build().unwrap(), xxh3.build().unwrap());
+        assert_eq!(
+            "T1DC33D4F0DCA405C02AF1D4860CA5894A05301D60E9915198060A7044C608A1E89A11BD2B2836520C1B007FD32079B226559FD998A0200725E75AFCEAC99F5881184A4B1AA2",
+            pearson.build().unwrap().hash()
+        );
+
+        let mut custom =
+            TxLshBuilder::with_hasher(BucketKind::Bucket128, ChecksumKind::OneByte, Fnv);
+        custom.update(LOREM_0);
+        let hash = custom.build().unwrap().hash();
+        assert!(hash.starts_with("Z1"));
+        assert_eq!(hash, TxLsh::from_str(&hash).unwrap().hash());
+
+        // A custom version on its own has no hash function to build with.
+        let mut unbound = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::custom("Z1").unwrap(),
+        );
+        unbound.update(LOREM_0);
+        assert!(matches!(unbound.build(), Err(TxLshError::UnboundVersion)));
+
+        assert!(Version::custom("Z1").is_ok());
+        assert!(Version::custom("Z12").is_err());
+        assert!(Version::custom("A1").is_err());
+        assert!(Version::custom("W1").is_err());
+        assert!(Version::custom("Y").is_err());
+        // Built-in prefixes cannot be registered, and custom versions are only made by
+        // registration, so custom digests never pass for built-in ones.
+        assert!(Version::custom("T1").is_err());
+        assert!(Version::custom("X2").is_err());
+    }
+
+    #[test]
+    fn test_keyed() {
+        fn digest<H: TripletHasher>(hasher: H) -> TxLsh {
+            let mut builder =
+                TxLshBuilder::with_hasher(BucketKind::Bucket128, ChecksumKind::OneByte, hasher);
+            builder.update(LOREM_0);
+            builder.build().unwrap()
+        }
+
+        let public = digest(Pearson::default());
+        let (a, b) = (
+            digest(Pearson::keyed(b"alpha")),
+            digest(Pearson::keyed(b"beta")),
+        );
+        assert_eq!(a, digest(Pearson::keyed(b"alpha")));
+        assert_ne!(a.codes, public.codes);
+        assert_ne!(a.codes, b.codes);
+
+        let hash = a.hash();
+        assert!(hash.starts_with("T1K"));
+        assert_eq!(a, TxLsh::from_str(&hash).unwrap());
+        assert_eq!(0, a.try_diff(&a, true).unwrap());
+        assert!(a.try_diff(&b, true).is_err());
+        assert!(a.try_diff(&public, true).is_err());
+
+        let x = digest(Xxh3::keyed(b"alpha"));
+        assert!(x.hash().starts_with("X1K"));
+        assert_ne!(x.codes, digest(Xxh3::default()).codes);
+    }
+
+    #[test]
+    fn test_run_filter() {
+        let padded = [LOREM_0, &[0; 1000], LOREM_0].concat();
+        let build = |filter, data: &[&[u8]]| {
+            let mut builder = TxLshBuilder::new(
+                BucketKind::Bucket128,
+                ChecksumKind::ThreeByte,
+                Version::Version4,
+            )
+            .with_run_filter(filter);
+            for chunk in data {
+                builder.update(chunk);
+            }
+            builder.build().unwrap()
+        };
+
+        let capped = build(RunFilter::Cap(16), &[&padded[..500], &padded[500..]]);
+        let expected = build(RunFilter::Off, &[LOREM_0, &[0; 16], LOREM_0]);
+        assert_eq!(
+            (&expected.checksum, &expected.codes, expected.len),
+            (&capped.checksum, &capped.codes, capped.len)
+        );
+
+        let hash = capped.hash();
+        assert!(hash.starts_with("T1R10010"));
+        assert_eq!(capped, TxLsh::from_str(&hash).unwrap());
+
+        // Markers are only parsed in the order in which they are encoded, each at most once.
+        let body = &hash["T1R10010".len()..];
+        assert!(TxLsh::from_str(&format!("T1W8R10010{body}")).is_ok());
+        assert!(TxLsh::from_str(&format!("T1R10010W8{body}")).is_err());
+        assert!(TxLsh::from_str(&format!("T1R10010R10010{body}")).is_err());
+        // Hex fields take no sign.
+        assert!(TxLsh::from_str(&format!("T1R1+010{body}")).is_err());
+        assert!(TxLsh::from_str(&format!("T1R10010+{}", &body[1..])).is_err());
+
+        // The text has runs of two bytes, kept by the filter, and ends with one still pending.
+        let skipped = build(RunFilter::Skip(8), &[&padded[..500], &padded[500..], b"ll"]);
+        let expected = build(RunFilter::Off, &[LOREM_0, LOREM_0, b"ll"]);
+        assert_eq!(
+            (&expected.checksum, &expected.codes, expected.len),
+            (&skipped.checksum, &skipped.codes, skipped.len)
+        );
+    }
+
+    #[test]
+    fn test_sampling() {
+        let data: Vec<u8> = (0..1 << 20)
+            .map(|ii: u32| (ii.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let sampled = |sampling| {
+            let mut builder = TxLshBuilder::new(
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Version4,
+            );
+            builder
+                .update_sampled(&mut std::io::Cursor::new(&data), sampling)
+                .unwrap();
+            builder.build().unwrap()
+        };
+
+        let sampling = Sampling::new(4096, 1. / 8., 7).unwrap();
+        let mut expected = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        let offsets: Vec<_> = sampling.block_offsets(data.len() as u64).collect();
+        assert_eq!(32, offsets.len());
+        for offset in offsets {
+            expected.update(&data[offset as usize..][..4096]);
+        }
+
+        let result = sampled(sampling);
+        assert_eq!(expected.build().unwrap().codes, result.codes);
+        assert_ne!(
+            result.codes,
+            sampled(Sampling::new(4096, 1. / 8., 8).unwrap()).codes
+        );
+
+        let hash = result.hash();
+        assert!(hash.starts_with("T1P0C00080007"));
+        assert_eq!(result, TxLsh::from_str(&hash).unwrap());
+        assert!(Sampling::new(1000, 0.5, 0).is_err());
+        for ratio in [0., -0.5, 1.5, f64::NAN, 1e-6] {
+            assert!(Sampling::new(1024, ratio, 0).is_err());
+        }
+        assert!(Sampling::new(1024, 1., 0).is_ok());
+
+        // Data processed in full cannot be marked as sampled.
+        let mut builder = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        builder.update(&data[..100]);
+        assert!(matches!(
+            builder.update_sampled(&mut std::io::Cursor::new(&data), sampling),
+            Err(TxLshError::InvalidSampling)
+        ));
+        builder.reset();
+        assert!(builder
+            .update_sampled(&mut std::io::Cursor::new(&data), sampling)
+            .is_ok());
+
+        // Nor can data processed in full be added to a sample, even through a snapshot.
+        builder.update(b"more");
+        assert!(matches!(builder.build(), Err(TxLshError::InvalidSampling)));
+        let mut restored = builder.clone();
+        restored.reset();
+        restored.restore(&builder.snapshot()).unwrap();
+        assert!(matches!(restored.build(), Err(TxLshError::InvalidSampling)));
+
+        // A failed read leaves the builder as it was.
+        struct Failing<'a>(std::io::Cursor<&'a [u8]>, usize);
+        impl Read for Failing<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.1 = self.1.checked_sub(1).ok_or(ErrorKind::UnexpectedEof)?;
+                self.0.read(buf)
+            }
+        }
+        impl Seek for Failing<'_> {
+            fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+                self.0.seek(pos)
+            }
+        }
+
+        builder.reset();
+        let initial = builder.snapshot();
+        let mut reader = Failing(std::io::Cursor::new(&data), 10);
+        assert!(matches!(
+            builder.update_sampled(&mut reader, sampling),
+            Err(TxLshError::Io(_))
+        ));
+        assert_eq!(initial, builder.snapshot());
+    }
+
+    #[test]
+    fn test_merge() {
+        let data = [LOREM_0, LOREM_0].concat();
+        let template = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::Version4,
+        )
+        .with_window(WindowKind::Window7);
+        let mut sequential = template.clone();
+        sequential.update(&data);
+        let expected = sequential.build().unwrap();
+
+        for (x, y) in [(0, 1), (3, 5), (4, 200), (300, 304), (500, data.len())] {
+            let mut parts = [template.clone(), template.clone(), template.clone()];
+            parts[0].update(&data[..x]);
+            parts[1].update(&data[x..y]);
+            parts[2].update(&data[y..]);
+
+            let merged = TxLshBuilder::merge(&parts[0], &parts[1]).unwrap();
+            let mut merged = TxLshBuilder::merge(&merged, &parts[2]).unwrap();
+            assert!(matches!(merged.build(), Err(TxLshError::ChecksumPending)));
+
+            // Nothing is pending over the first part merged into an empty builder.
+            if x > 0 {
+                merged.continue_checksum(&data[x..y]);
+            }
+            merged.continue_checksum(&data[y..]);
+            assert_eq!(expected, merged.build().unwrap());
+        }
+
+        let merged = TxLshBuilder::merge(&template, &sequential).unwrap();
+        assert_eq!(expected, merged.build().unwrap());
+
+        let mut xxh3 = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV1,
+        );
+        assert!(TxLshBuilder::merge(&template, &xxh3).is_err());
+
+        let mut right = xxh3.clone();
+        xxh3.update(&data[..100]);
+        right.update(&data[100..]);
+        let mut merged = TxLshBuilder::merge(&xxh3, &right).unwrap();
+        merged.continue_checksum(&data[100..]);
+        xxh3.update(&data[100..]);
+        assert_eq!(xxh3.build().unwrap(), merged.build().unwrap());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let template = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV1,
+        );
+        let mut sequential = template.clone();
+        sequential.update(LOREM_0);
+
+        let mut builder = template.clone();
+        builder.update(&LOREM_0[..150]);
+        let snapshot = builder.snapshot();
+
+        let mut restored = template.clone();
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(builder, restored);
+        restored.update(&LOREM_0[150..]);
+        assert_eq!(sequential.build().unwrap(), restored.build().unwrap());
+
+        let mut other = template.clone().with_window(WindowKind::Window6);
+        assert!(matches!(
+            other.restore(&snapshot),
+            Err(TxLshError::IncompatibleDigests)
+        ));
+        let unchanged = restored.clone();
+        assert!(matches!(
+            restored.restore(&snapshot[..snapshot.len() - 1]),
+            Err(TxLshError::InvalidBytes)
+        ));
+        assert_eq!(unchanged, restored);
+
+        // A reset builder gives the same three-byte checksum as a fresh one.
+        sequential.reset();
+        sequential.update(&LOREM_0[..150]);
+        assert_eq!(builder.build().unwrap(), sequential.build().unwrap());
+    }
+
+    #[test]
+    fn test_update_reader() {
+        use std::io::Write;
+
+        let data = LOREM_0.repeat(400);
+        let mut expected = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::ThreeByte,
+            Version::Version4,
+        );
+        let mut builder = expected.clone();
+        expected.update(&data);
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&data).unwrap();
+        let mut reports = Vec::new();
+        builder
+            .update_file(file.path(), |done, total| reports.push((done, total)), None)
+            .unwrap();
+
+        let total = Some(data.len() as u64);
+        assert_eq!(Some(&(data.len() as u64, total)), reports.last());
+        assert_eq!(expected.build().unwrap(), builder.build().unwrap());
+
+        let cancel = CancelToken::new();
+        let mut cancelled = builder.clone();
+        let result = cancelled.update_reader(
+            &mut std::io::Cursor::new(&data),
+            None,
+            |_, _| cancel.cancel(),
+            Some(&cancel),
+        );
+        assert!(matches!(result, Err(TxLshError::Cancelled)));
+        assert_eq!(builder, cancelled);
+    }
+
+    #[test]
+    fn test_update_chunks() {
+        let data = LOREM_0.repeat(20);
+
+        for ver in [Version::Original, Version::Version4, Version::TxLshV1] {
+            for checksum in [ChecksumKind::OneByte, ChecksumKind::ThreeByte] {
+                for window in [
+                    WindowKind::Window4,
+                    WindowKind::Window5,
+                    WindowKind::Window8,
+                ] {
+                    let template =
+                        TxLshBuilder::new(BucketKind::Bucket256, checksum, ver).with_window(window);
+
+                    // Straightforward processing of every window, one byte at a time.
+                    let (size, len) = (window.window_size(), checksum.checksum_len());
+                    let mut buckets = [0; BUCKET_SIZE];
+                    let mut expected = vec![0; len];
+                    for end in (size - 1)..data.len() {
+                        let (newest, prev) = (data[end], data[end - 1]);
+                        expected[0] = ver.hash(0, newest, prev, expected[0]);
+                        for kk in 1..len {
+                            expected[kk] = ver.hash(expected[kk - 1], newest, prev, expected[kk]);
+                        }
+                        for &(salt, x, y, z) in window.triplets() {
+                            let r = ver.hash(salt, data[end - x], data[end - y], data[end - z]);
+                            buckets[r as usize] += 1;
+                        }
+                    }
+
+                    let mut whole = template.clone();
+                    whole.update(&data);
+                    assert_eq!(&buckets[..], whole.buckets());
+                    assert_eq!(expected, whole.histogram().checksum);
+
+                    for chunk_len in [1, 3, 7, 4101] {
+                        let mut chunked = template.clone();
+                        for chunk in data.chunks(chunk_len) {
+                            chunked.update(chunk);
+                        }
+                        assert_eq!(whole, chunked);
+                    }
+                }
+            }
+        }
+
+        // The inlined xxh3 matches the library whatever the seed.
+        let keyed = Xxh3::keyed(b"key");
+        for (ii, jj) in (0..=255).zip((0..=255).rev()) {
+            assert_eq!(
+                TripletHasher::hash(&keyed, ii, jj, ii ^ jj, 7),
+                TripletMixer::hash(&keyed, ii, jj, ii ^ jj, 7)
+            );
+        }
+    }
+
+    #[test]
+    fn test_wide() {
+        let mut state = 7u64;
+        let data: Vec<u8> = (0..(1 << 16))
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect();
+        let template = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV2,
+        );
+
+        // One xxh3 of the window per position: the lowest byte feeds the checksum and the next six
+        // are the buckets.
+        let mut buckets = [0; BUCKET_SIZE];
+        let mut checksum = [0u8; 3];
+        for window in data.windows(5) {
+            let h = xxhash_rust::xxh3::xxh3_64(window).to_le_bytes();
+            let mut salt = 0;
+            for item in checksum.iter_mut() {
+                *item = pearson_h2(salt, h[0], *item);
+                salt = *item;
+            }
+            for &r in &h[1..7] {
+                buckets[r as usize] += 1;
+            }
+        }
+
+        let mut wide = template.clone();
+        wide.update(&data);
+        assert_eq!(&buckets[..], wide.buckets());
+        assert_eq!(checksum.to_vec(), wide.histogram().checksum);
+
+        let mut chunked = template.clone();
+        for chunk in data.chunks(1000) {
+            chunked.update(chunk);
+        }
+        assert_eq!(wide, chunked);
+
+        let (mut left, mut right) = (template.clone(), template.clone());
+        left.update(&data[..3]);
+        right.update(&data[3..]);
+        let mut merged = TxLshBuilder::merge(&left, &right).unwrap();
+        merged.continue_checksum(&data[3..]);
+        assert_eq!(wide.build().unwrap(), merged.build().unwrap());
+
+        let digest = wide.build().unwrap();
+        assert!(digest.hash().starts_with("X2"));
+        assert_eq!(digest, digest.hash().parse().unwrap());
+
+        // A wide hasher used on its own hashes whole windows too, and never single triplets.
+        let hasher = Xxh3::default().wide();
+        let mut explicit =
+            TxLshBuilder::with_hasher(BucketKind::Bucket256, ChecksumKind::ThreeByte, hasher);
+        explicit.update(&data);
+        assert_eq!(digest, explicit.build().unwrap());
+        assert!(
+            std::panic::catch_unwind(|| crate::TripletHasher::hash(&hasher, 0, 1, 2, 3)).is_err()
+        );
+
+        // Buckets are as evenly filled as with X1, by a chi-squared statistic over 255 degrees of
+        // freedom.
+        let mut narrow = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV1,
+        );
+        narrow.update(&data);
+        let chi_squared = |buckets: &[u64]| {
+            let expected = buckets.iter().sum::<u64>() as f64 / BUCKET_SIZE as f64;
+            buckets
+                .iter()
+                .map(|&count| (count as f64 - expected).powi(2) / expected)
+                .sum::<f64>()
+        };
+        let (x1, x2) = (chi_squared(&narrow.buckets()), chi_squared(&wide.buckets()));
+        assert!(x2 < 330. && x1 < 330., "{x1} {x2}");
+        assert!(matches!(
+            digest.try_diff(&narrow.build().unwrap(), true),
+            Err(TxLshError::IncompatibleDigests)
+        ));
+    }
+
+    #[test]
+    fn test_build_into() {
+        let mut first = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV1,
+        )
+        .with_quantiles(QuantileKind::Octile);
+        first.update(LOREM_0);
+        let mut digest = first.build().unwrap();
+
+        for (bucket, checksum, ver, filter) in [
+            (
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Original,
+                RunFilter::Off,
+            ),
+            (
+                BucketKind::Bucket256,
+                ChecksumKind::ThreeByte,
+                Version::TxLshV2,
+                RunFilter::Off,
+            ),
+            (
+                BucketKind::Bucket128,
+                ChecksumKind::ThreeByte,
+                Version::Version4,
+                RunFilter::Skip(8),
+            ),
+        ] {
+            let mut builder = TxLshBuilder::new(bucket, checksum, ver).with_run_filter(filter);
+            builder.update(LOREM_0);
+            builder.update(b"ll");
+
+            builder.build_into(&mut digest).unwrap();
+            assert_eq!(builder.build().unwrap(), digest);
+        }
+
+        // A failed build leaves the digest untouched.
+        let expected = digest.clone();
+        let mut builder = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        builder.update(b"short");
+        assert!(builder.build_into(&mut digest).is_err());
+        assert_eq!(expected, digest);
+    }
+
+    #[test]
+    fn test_build_into_allocations() {
+        let dense = TripletSelection::new(&[(2, 0, 1, 2), (3, 1, 2, 3), (5, 0, 2, 4)]).unwrap();
+        let builders = [
+            TxLshBuilder::new(
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Version4,
+            ),
+            TxLshBuilder::new(
+                BucketKind::Bucket256,
+                ChecksumKind::ThreeByte,
+                Version::TxLshV1,
+            )
+            .with_window(WindowKind::Window7)
+            .with_quantiles(QuantileKind::Hexadecile),
+            TxLshBuilder::new(
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Version4,
+            )
+            .with_triplets(dense)
+            .unwrap(),
+        ];
+
+        for mut builder in builders {
+            builder.update(LOREM_0);
+            // The first build sizes the storage of the digest.
+            let mut digest = builder.build().unwrap();
+
+            let before = ALLOCATIONS.with(Cell::get);
+            for _ in 0..100 {
+                builder.build_into(&mut digest).unwrap();
+            }
+            assert_eq!(before, ALLOCATIONS.with(Cell::get));
+            assert_eq!(builder.build().unwrap(), digest);
+        }
+    }
+
+    #[test]
+    fn test_build_batch() {
+        let data = [LOREM_0, b"short", &LOREM_0[100..], &[7; 300]].concat();
+        let offsets = [
+            0,
+            LOREM_0.len(),
+            LOREM_0.len() + 5,
+            2 * LOREM_0.len() - 95,
+            data.len(),
+        ];
+
+        for (ver, filter) in [
+            (Version::Version4, RunFilter::Off),
+            (Version::TxLshV2, RunFilter::Off),
+            (Version::Original, RunFilter::Cap(8)),
+        ] {
+            let template = TxLshBuilder::new(BucketKind::Bucket128, ChecksumKind::ThreeByte, ver)
+                .with_run_filter(filter);
+            let expected: Vec<_> = offsets
+                .windows(2)
+                .map(|pair| {
+                    let mut builder = template.clone();
+                    builder.update(&data[pair[0]..pair[1]]);
+                    builder.build().ok()
+                })
+                .collect();
+            assert!(expected[0].is_some() && expected[1].is_none());
+
+            let mut dirty = template.clone();
+            dirty.update(LOREM_0);
+            let batch = dirty.build_batch_offsets(&data, &offsets);
+            assert_eq!(
+                expected,
+                batch.into_iter().map(Result::ok).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_update_parallel() {
+        let mut state = 3u64;
+        let data: Vec<u8> = (0..(5 * MIN_PARALLEL_RANGE + 17))
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 58) as u8
+            })
+            .collect();
+
+        for (bucket, checksum, ver) in [
+            (
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Version4,
+            ),
+            (
+                BucketKind::Bucket256,
+                ChecksumKind::ThreeByte,
+                Version::TxLshV1,
+            ),
+            (
+                BucketKind::Bucket256,
+                ChecksumKind::ThreeByte,
+                Version::TxLshV2,
+            ),
+        ] {
+            // Some data processed beforehand, shorter than a window.
+            let mut sequential = TxLshBuilder::new(bucket, checksum, ver);
+            sequential.update(&LOREM_0[..3]);
+            let mut parallel = sequential.clone();
+
+            sequential.update(&data);
+            parallel.update_parallel(&data, 4);
+            assert_eq!(sequential.buckets(), parallel.buckets());
+            assert_eq!(sequential.build().unwrap(), parallel.build().unwrap());
+        }
+
+        let template = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::ThreeByte,
+            Version::Version4,
+        );
+        let mut sequential = template.clone();
+        sequential.update(&data);
+        assert_eq!(
+            sequential.build().unwrap(),
+            hash_parallel(&data, 4, &template).unwrap()
+        );
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_update_file_parallel() {
+        use std::io::Write;
+
+        let data = LOREM_0.repeat(3 * MIN_PARALLEL_RANGE / LOREM_0.len());
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&data).unwrap();
+
+        let template = TxLshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::ThreeByte,
+            Version::Version4,
+        );
+        let mut sequential = template.clone();
+        sequential.update(&data);
+        let mut parallel = template.clone();
+        parallel.update_file_parallel(file.path(), 3).unwrap();
+        assert_eq!(sequential.build().unwrap(), parallel.build().unwrap());
+        assert_eq!(
+            sequential.build().unwrap(),
+            hash_file_parallel(file.path(), 3, &template).unwrap()
         );
-        txlsh.update_from(LOREM_0, 0, lorem.len());
-        assert_eq!(
-            "X18B6AADF05C1C6293150EE83C25635D4C68650291D7C57D492757E52174B7800D6577546B39F325196422CA6DA78F6553446016F5B138B8F8B97410A0D3930ACD3FBCB99991",
-            txlsh.build().unwrap().hash()
-        )
     }
 }
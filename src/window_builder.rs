@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+
+use crate::{
+    hash_funcs::TripletHasher, RunFilter, TxLsh, TxLshBuilder, TxLshError, TxLshHistogram, Version,
+};
+
+/// A builder digesting only the last bytes of a stream, e.g. the last megabytes of a log.
+///
+/// Bucket counts are additive, so the triplets of a byte leaving the window are subtracted when
+/// it is evicted and the buckets always match those of the retained bytes. The checksum is a chain
+/// over the whole input and cannot be unwound; it is recomputed over the retained bytes by
+/// [`TxLshWindowBuilder::build`], which costs one pass over the window per checksum byte. The
+/// resulting digest is identical to that of a [`TxLshBuilder`] fed the retained bytes only.
+#[derive(Clone, Debug)]
+pub struct TxLshWindowBuilder<H: TripletHasher = Version> {
+    builder: TxLshBuilder<H>,
+    capacity: usize,
+    retained: VecDeque<u8>,
+}
+
+impl<H: TripletHasher> TxLshWindowBuilder<H> {
+    /// Constructs a builder retaining the last ```capacity``` bytes, with the settings of
+    /// ```builder```.
+    ///
+    /// The builder is reset and its [`RunFilter`] is not applied. Fails if ```capacity``` is
+    /// smaller than the sliding window.
+    pub fn new(builder: TxLshBuilder<H>, capacity: usize) -> Result<Self, TxLshError> {
+        if capacity < builder.window_size() {
+            Err(TxLshError::InvalidCapacity)?
+        }
+
+        Ok(Self {
+            builder: builder.with_run_filter(RunFilter::Off),
+            capacity,
+            retained: VecDeque::with_capacity(capacity),
+        })
+    }
+
+    /// Returns the number of bytes currently in the window.
+    pub fn len(&self) -> usize {
+        self.retained.len()
+    }
+
+    /// Returns whether no data has been processed yet.
+    pub fn is_empty(&self) -> bool {
+        self.retained.is_empty()
+    }
+
+    /// Processes an input stream, evicting the oldest bytes beyond the capacity.
+    pub fn update(&mut self, data: &[u8]) {
+        let window = self.builder.window_size();
+
+        for chunk in data.chunks(self.capacity) {
+            self.builder.update(chunk);
+            self.retained.extend(chunk);
+
+            let excess = self.retained.len().saturating_sub(self.capacity);
+            if excess > 0 {
+                // Each evicted byte is the oldest of exactly one window.
+                let retained = self.retained.make_contiguous();
+                self.builder
+                    .remove_windows(&retained[..(excess + window - 1)]);
+                self.retained.drain(..excess);
+            }
+        }
+    }
+
+    /// Returns the full bucket histogram of the bytes in the window.
+    pub fn histogram(&self) -> TxLshHistogram {
+        let mut histogram = self.builder.histogram();
+        histogram.data_len = self.retained.len() as u64;
+        histogram.checksum = self.builder.checksum_of(self.retained.iter().copied());
+
+        histogram
+    }
+
+    /// Builds the digest of the bytes in the window.
+    pub fn build(&self) -> Result<TxLsh, TxLshError> {
+        self.builder.check_bound()?;
+        self.histogram().to_txlsh(self.builder.bucket_kind())
+    }
+
+    /// Clears the state of the builder, removing all data.
+    pub fn reset(&mut self) {
+        self.builder.reset();
+        self.retained.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BucketKind, ChecksumKind};
+
+    static LOREM: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+
+    #[test]
+    fn test_window_builder() {
+        let template = TxLshBuilder::new(
+            BucketKind::Bucket256,
+            ChecksumKind::ThreeByte,
+            Version::TxLshV1,
+        );
+        let mut windowed = TxLshWindowBuilder::new(template.clone(), 300).unwrap();
+
+        for chunk in LOREM.chunks(7) {
+            windowed.update(chunk);
+        }
+        windowed.update(&LOREM[..120]);
+
+        let mut expected = template.clone();
+        expected.update(&[LOREM, &LOREM[..120]].concat()[(LOREM.len() + 120 - 300)..]);
+
+        assert_eq!(300, windowed.len());
+        assert_eq!(expected.build().unwrap(), windowed.build().unwrap());
+        assert!(TxLshWindowBuilder::new(template, 4).is_err());
+    }
+}